@@ -0,0 +1,173 @@
+// Copyright (c) 2024 Venkatesh Omkaram
+
+use clonehunter::common::{
+    core::{log, print_duplicates, DuplicatesSummary, LogLevel, PrinterConfig, SimilarPair, Stage},
+    hasher::Digest,
+};
+use fastcdc::v2020::StreamCDC;
+use hashbrown::HashMap;
+use human_bytes::human_bytes;
+use indicatif::ProgressBar;
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use std::{
+    fs::File,
+    io,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    hunt::{collapse_hardlinks, progress_style, split_references, stage_stats, HuntConfig},
+    similar::group_pairs,
+};
+
+/// A chunk found in more files than this (zero blocks, common headers, or the chunks of a file copied to many places)
+/// does not add its bytes to every pair of its files
+const COMMON_CHUNK_LIMIT: usize = 64;
+
+/// The files sharing a common chunk are sorted by their size, and the chunk only adds its bytes to the pairs of each of
+/// them with this many of the files which follow it. The many copies of a file still end up in one group this way
+const COMMON_CHUNK_WINDOW: usize = 8;
+
+/// A file reduced to the checksums and lengths of its content-defined chunks
+struct ChunkedFile {
+    path: PathBuf,
+    size: u64,
+    chunks: Vec<(Digest, u64)>,
+}
+
+/// Hunts for files which share large regions of their content, such as VM images, database dumps and rotated logs.
+/// Every file is split with the FastCDC rolling hash, which cuts the chunks where the content says so rather than at
+/// fixed offsets, so a region shifted by an insertion still yields the same chunks. Two files share the bytes of the
+/// distinct chunks they have in common, and the pairs sharing at least the threshold of the smaller file are joined
+/// into groups. A chunk common to many files only counts for the files which are next to each other by size. The bytes a deduplicating file system would save are estimated from the chunks of all the files.
+pub fn hunt_overlap(
+    files: Vec<PathBuf>,
+    threshold: f64,
+    chunk_size: u32,
+    config: HuntConfig,
+    mut print_config: PrinterConfig,
+) -> DuplicatesSummary {
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(config.threads.into())
+        .build()
+        .unwrap();
+
+    // The hardlinks of a file share all of their content without taking any more space
+    let (files, _) = collapse_hardlinks(files);
+
+    log(LogLevel::INFO, "Splitting the files into chunks");
+
+    let pb = ProgressBar::new(files.len() as u64);
+    pb.set_style(progress_style());
+    pb.set_message("files chunked");
+
+    let chunked_files: Vec<ChunkedFile> = pool.install(|| {
+        files
+            .par_iter()
+            .filter_map(|path| {
+                let chunked_file = match chunk_file(path, chunk_size, &config) {
+                    Ok(chunked_file) => chunked_file,
+                    Err(e) => {
+                        log(LogLevel::ERROR, format!("File {:?} {:?}", path, e.kind()).as_str());
+                        None
+                    }
+                };
+                pb.inc(1);
+                chunked_file
+            })
+            .collect()
+    });
+
+    pb.finish_and_clear();
+
+    let mut files_by_chunk: HashMap<&Digest, (u64, Vec<usize>)> = HashMap::new();
+    for (i, chunked_file) in chunked_files.iter().enumerate() {
+        for (digest, length) in &chunked_file.chunks {
+            let (_, indices) = files_by_chunk.entry(digest).or_insert_with(|| (*length, Vec::new()));
+            // The files are visited in order, so a chunk repeated inside a file is only counted once for it
+            if indices.last() != Some(&i) {
+                indices.push(i);
+            }
+        }
+    }
+
+    let total_bytes: u64 = chunked_files.iter().map(|x| x.size).sum();
+    let distinct_bytes: u64 = files_by_chunk.values().map(|x| x.0).sum();
+    let chunk_count: usize = chunked_files.iter().map(|x| x.chunks.len()).sum();
+
+    log(
+        LogLevel::INFO,
+        format!(
+            "Split {} file(s) of {} into {} chunk(s), of which {} are distinct",
+            chunked_files.len(),
+            human_bytes(total_bytes as f64),
+            chunk_count,
+            files_by_chunk.len()
+        )
+        .as_str(),
+    );
+
+    log(LogLevel::INFO, "Comparing the shared chunks");
+
+    let mut shared: HashMap<(usize, usize), u64> = HashMap::new();
+    for (length, indices) in files_by_chunk.values().filter(|x| x.1.len() > 1) {
+        let mut indices = indices.clone();
+        let window = if indices.len() > COMMON_CHUNK_LIMIT {
+            indices.sort_by_key(|&x| (chunked_files[x].size, x));
+            COMMON_CHUNK_WINDOW
+        } else {
+            indices.len()
+        };
+        for (n, &a) in indices.iter().enumerate() {
+            for &b in indices[n + 1..].iter().take(window) {
+                *shared.entry((a.min(b), a.max(b))).or_default() += length;
+            }
+        }
+    }
+
+    let pairs: Vec<SimilarPair> = shared
+        .into_iter()
+        .filter_map(|((a, b), shared_bytes)| {
+            let smaller = chunked_files[a].size.min(chunked_files[b].size);
+            let score = (shared_bytes as f64 / smaller as f64).min(1.0);
+            (score >= threshold).then(|| SimilarPair {
+                a: chunked_files[a].path.clone(),
+                b: chunked_files[b].path.clone(),
+                score,
+                shared_bytes: Some(shared_bytes),
+            })
+        })
+        .collect();
+
+    let groups = split_references(group_pairs(pairs, Stage::ChunkOverlap), &config.references);
+
+    let grouped: usize = groups.iter().map(|x| x.paths.len() + x.references.len()).sum();
+    let stages = vec![stage_stats(Stage::ChunkOverlap, chunked_files.len(), grouped)];
+
+    print_config.dedupable_bytes = Some(total_bytes.saturating_sub(distinct_bytes));
+
+    print_duplicates(groups, stages, print_config)
+}
+
+// Splits a file into content-defined chunks of about the average size, and hashes each chunk. Empty files are skipped
+fn chunk_file(path: &Path, chunk_size: u32, config: &HuntConfig) -> io::Result<Option<ChunkedFile>> {
+    let size = path.metadata()?.len();
+    if size == 0 {
+        return Ok(None);
+    }
+
+    let mut chunks = Vec::new();
+    for chunk in StreamCDC::new(File::open(path)?, chunk_size / 4, chunk_size, chunk_size * 4) {
+        let chunk = chunk.map_err(io::Error::from)?;
+        let mut hasher = config.hash.hasher();
+        hasher.update(&chunk.data);
+        chunks.push((hasher.finish(), chunk.length as u64));
+    }
+
+    Ok(Some(ChunkedFile {
+        path: path.to_path_buf(),
+        size,
+        chunks,
+    }))
+}
+
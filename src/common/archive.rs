@@ -0,0 +1,189 @@
+// Copyright (c) 2024 Venkatesh Omkaram
+
+// Archives whose members are hunted like regular files. A member is addressed by a virtual path made of the path of
+// the archive, the separator and the path of the member inside the archive, for example `backup.zip!/dir/file.txt`
+use std::{
+    fs::File,
+    io::{self, BufReader, Read},
+    path::{Path, PathBuf},
+};
+
+use flate2::read::GzDecoder;
+use hashbrown::HashMap;
+
+use crate::common::{
+    config::HashAlgorithm,
+    hasher::{stream_checksums, Digest},
+};
+
+/// Separates the path of an archive from the path of a member inside it
+pub const ARCHIVE_SEPARATOR: &str = "!/";
+
+/// The kinds of archives whose members can be read
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ArchiveKind {
+    Zip,
+    Tar,
+    TarGz,
+}
+
+/// The size and the checksums of an archive member, all computed in a single pass over the archive
+#[derive(Clone, Debug)]
+pub struct ArchiveMember {
+    pub size: u64,
+    pub partial: Digest,
+    pub full: Digest,
+}
+
+/// The members of every scanned archive, keyed by their virtual path
+#[derive(Default)]
+pub struct ArchiveIndex {
+    members: HashMap<PathBuf, ArchiveMember>,
+}
+
+impl ArchiveIndex {
+    pub fn insert(&mut self, path: PathBuf, member: ArchiveMember) {
+        self.members.insert(path, member);
+    }
+
+    pub fn get(&self, path: &Path) -> Option<&ArchiveMember> {
+        self.members.get(path)
+    }
+
+    pub fn paths(&self) -> impl Iterator<Item = &PathBuf> {
+        self.members.keys()
+    }
+
+    pub fn len(&self) -> usize {
+        self.members.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+}
+
+fn archive_kind(path: &Path) -> Option<ArchiveKind> {
+    let name = path.file_name()?.to_string_lossy().to_lowercase();
+
+    if name.ends_with(".zip") {
+        Some(ArchiveKind::Zip)
+    } else if name.ends_with(".tar") {
+        Some(ArchiveKind::Tar)
+    } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+        Some(ArchiveKind::TarGz)
+    } else {
+        None
+    }
+}
+
+/// Whether the file is a zip, tar or gzipped tar archive, judging by its name
+pub fn is_archive(path: &Path) -> bool {
+    archive_kind(path).is_some()
+}
+
+/// Splits a virtual path into the path of the archive and the path of the member inside it
+pub fn split_member(path: &Path) -> Option<(PathBuf, String)> {
+    let path = path.to_string_lossy();
+
+    path.match_indices(ARCHIVE_SEPARATOR).find_map(|(i, _)| {
+        let archive = Path::new(&path[..i]);
+        (is_archive(archive) && archive.is_file())
+            .then(|| (archive.to_path_buf(), path[i + ARCHIVE_SEPARATOR.len()..].to_owned()))
+    })
+}
+
+/// Whether the path points inside an archive rather than to a file on the disk
+pub fn is_member(path: &Path) -> bool {
+    !path.exists() && split_member(path).is_some()
+}
+
+/// Reads every regular file inside the archive and returns the virtual path, the size and the checksums of each one
+pub fn scan_archive(path: &Path, algorithm: HashAlgorithm) -> io::Result<Vec<(PathBuf, ArchiveMember)>> {
+    let mut members = Vec::new();
+    let mut add_member = |name: &str, size: u64, reader: &mut dyn Read| -> io::Result<()> {
+        let (partial, full) = stream_checksums(reader, size, algorithm)?;
+        members.push((member_path(path, name), ArchiveMember { size, partial, full }));
+        Ok(())
+    };
+
+    match archive_kind(path) {
+        Some(ArchiveKind::Zip) => {
+            let mut archive = zip::ZipArchive::new(BufReader::new(File::open(path)?)).map_err(io::Error::other)?;
+            for i in 0..archive.len() {
+                let mut file = archive.by_index(i).map_err(io::Error::other)?;
+                if file.is_file() {
+                    let name = file.name().to_owned();
+                    add_member(&name, file.size(), &mut file)?;
+                }
+            }
+        }
+        Some(ArchiveKind::Tar) => scan_tar(tar::Archive::new(BufReader::new(File::open(path)?)), &mut add_member)?,
+        Some(ArchiveKind::TarGz) => {
+            scan_tar(tar::Archive::new(GzDecoder::new(BufReader::new(File::open(path)?))), &mut add_member)?
+        }
+        None => {}
+    }
+
+    Ok(members)
+}
+
+fn scan_tar<R: Read>(
+    mut archive: tar::Archive<R>,
+    add_member: &mut dyn FnMut(&str, u64, &mut dyn Read) -> io::Result<()>,
+) -> io::Result<()> {
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if entry.header().entry_type().is_file() {
+            let name = entry.path()?.to_string_lossy().into_owned();
+            let size = entry.size();
+            add_member(&name, size, &mut entry)?;
+        }
+    }
+    Ok(())
+}
+
+/// Reads the whole content of an archive member
+pub fn read_member(path: &Path) -> io::Result<Vec<u8>> {
+    let (archive_path, name) = split_member(path)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Not a member of an archive"))?;
+    let mut content = Vec::new();
+
+    let found = match archive_kind(&archive_path) {
+        Some(ArchiveKind::Zip) => {
+            let mut archive =
+                zip::ZipArchive::new(BufReader::new(File::open(&archive_path)?)).map_err(io::Error::other)?;
+            archive.by_name(&name).map_err(io::Error::other)?.read_to_end(&mut content)?;
+            true
+        }
+        Some(ArchiveKind::Tar) => {
+            read_tar_member(tar::Archive::new(BufReader::new(File::open(&archive_path)?)), &name, &mut content)?
+        }
+        Some(ArchiveKind::TarGz) => {
+            let reader = GzDecoder::new(BufReader::new(File::open(&archive_path)?));
+            read_tar_member(tar::Archive::new(reader), &name, &mut content)?
+        }
+        None => false,
+    };
+
+    if found {
+        Ok(content)
+    } else {
+        Err(io::Error::new(io::ErrorKind::NotFound, "The archive member was not found"))
+    }
+}
+
+fn read_tar_member<R: Read>(mut archive: tar::Archive<R>, name: &str, content: &mut Vec<u8>) -> io::Result<bool> {
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if entry.header().entry_type().is_file() && entry.path()?.to_string_lossy().trim_start_matches("./") == name {
+            entry.read_to_end(content)?;
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+fn member_path(archive: &Path, name: &str) -> PathBuf {
+    PathBuf::from(format!("{}{}{}", archive.display(), ARCHIVE_SEPARATOR, name.trim_start_matches("./")))
+}
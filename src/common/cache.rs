@@ -0,0 +1,328 @@
+// Copyright (c) 2024 Venkatesh Omkaram
+
+// An on-disk cache of the content checksums, so that the files which did not change since the last hunt are not read again.
+// The cache file holds one record per line. A hunt appends the checksums it computed, and a later record of a file
+// overrides the earlier ones. The file is rewritten only when it is pruned, or when the overridden records make up most of it
+use std::{
+    env,
+    fs::{self, File},
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+use hashbrown::{HashMap, HashSet};
+use serde::{Deserialize, Serialize};
+
+#[cfg(target_os = "linux")]
+use std::os::unix::fs::MetadataExt;
+
+use crate::common::{
+    config::HashAlgorithm,
+    core::file_identity,
+    hasher::{full_checksum, partial_checksum, Digest},
+};
+
+const CACHE_FILE_NAME: &str = "hashes.jsonl";
+
+/// The cache file is rewritten once it holds this many times more records than there are entries
+const COMPACTION_RATIO: usize = 2;
+
+/// The cache identifies a file by its device and inode, which are only read on Linux
+pub const CACHE_SUPPORTED: bool = cfg!(target_os = "linux");
+
+/// Identifies a particular version of a file. A cached checksum is only reused when all the fields still match
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct CacheKey {
+    pub dev: u64,
+    pub ino: u64,
+    pub size: u64,
+    pub mtime_ns: i64,
+    pub algorithm: HashAlgorithm,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct CacheValue {
+    /// The path the file was last seen at. Only used to prune the cache
+    path: PathBuf,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    partial: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    full: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CacheRecord {
+    #[serde(flatten)]
+    key: CacheKey,
+    #[serde(flatten)]
+    value: CacheValue,
+}
+
+/// The kind of checksum stored in the cache
+#[derive(Clone, Copy, Debug)]
+pub enum ChecksumKind {
+    Partial,
+    Full,
+}
+
+/// Summary of the cache contents used by the `cache stats` command
+pub struct CacheStats {
+    pub file: PathBuf,
+    pub file_size: u64,
+    pub entries: usize,
+    pub partial_checksums: usize,
+    pub full_checksums: usize,
+    pub entries_by_algorithm: Vec<(HashAlgorithm, usize)>,
+}
+
+pub struct HashCache {
+    file: PathBuf,
+    entries: Mutex<HashMap<CacheKey, CacheValue>>,
+    /// The entries which changed since the cache was loaded or saved, so they are appended on the next save
+    changed: Mutex<HashSet<CacheKey>>,
+    /// The number of records in the cache file, including the overridden ones
+    file_records: AtomicUsize,
+    /// Set when entries were removed, which can only be saved by rewriting the file
+    rewrite: AtomicBool,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl HashCache {
+    /// The cache lives under $XDG_CACHE_HOME/clonehunter, falling back to ~/.cache/clonehunter
+    pub fn default_location() -> Option<PathBuf> {
+        let base = env::var_os("XDG_CACHE_HOME")
+            .filter(|x| Path::new(x).is_absolute())
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|x| PathBuf::from(x).join(".cache")));
+
+        base.map(|x| x.join("clonehunter").join(CACHE_FILE_NAME))
+    }
+
+    /// Loads the cache from the default location. A missing cache file gives an empty cache
+    pub fn open() -> io::Result<HashCache> {
+        let file = Self::default_location().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, "Could not figure out the cache directory")
+        })?;
+        Self::open_at(file)
+    }
+
+    /// Loads the cache from the given file. A line which cannot be read, such as the last line of a run which was
+    /// killed while saving, is skipped and dropped on the next rewrite
+    pub fn open_at(file: PathBuf) -> io::Result<HashCache> {
+        let mut entries = HashMap::new();
+        let mut file_records = 0;
+
+        match File::open(&file) {
+            Ok(f) => {
+                for line in BufReader::new(f).lines() {
+                    file_records += 1;
+                    if let Ok(record) = serde_json::from_str::<CacheRecord>(&line?) {
+                        entries.insert(record.key, record.value);
+                    }
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+
+        Ok(HashCache {
+            file,
+            entries: Mutex::new(entries),
+            changed: Mutex::new(HashSet::new()),
+            file_records: AtomicUsize::new(file_records),
+            rewrite: AtomicBool::new(false),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        })
+    }
+
+    /// Returns the checksum of the file, either from the cache or by hashing the file.
+    /// A freshly computed checksum is only stored when the file did not change while it was being hashed
+    pub fn checksum(&self, path: &Path, algorithm: HashAlgorithm, kind: ChecksumKind) -> io::Result<Digest> {
+        self.checksum_with(path, algorithm, kind, || match kind {
+            ChecksumKind::Partial => partial_checksum(path, algorithm),
+            ChecksumKind::Full => full_checksum(path, algorithm),
+        })
+    }
+
+    /// Returns the checksum of the file from the cache, or computes it with the given function when it is not cached
+    pub fn checksum_with(
+        &self,
+        path: &Path,
+        algorithm: HashAlgorithm,
+        kind: ChecksumKind,
+        compute: impl FnOnce() -> io::Result<Digest>,
+    ) -> io::Result<Digest> {
+        let key = cache_key(path, algorithm);
+
+        if let Some(key) = key {
+            if let Some(value) = self.entries.lock().unwrap().get(&key) {
+                let cached = match kind {
+                    ChecksumKind::Partial => value.partial.as_deref(),
+                    ChecksumKind::Full => value.full.as_deref(),
+                };
+                if let Some(digest) = cached.and_then(Digest::from_hex) {
+                    self.hits.fetch_add(1, Ordering::Relaxed);
+                    return Ok(digest);
+                }
+            }
+        }
+
+        self.misses.fetch_add(1, Ordering::Relaxed);
+
+        let digest = compute()?;
+
+        if let Some(key) = key.filter(|x| Some(*x) == cache_key(path, algorithm)) {
+            let mut entries = self.entries.lock().unwrap();
+            let value = entries.entry(key).or_insert_with(|| CacheValue {
+                path: path.to_path_buf(),
+                partial: None,
+                full: None,
+            });
+            value.path = path.to_path_buf();
+            match kind {
+                ChecksumKind::Partial => value.partial = Some(digest.to_string()),
+                ChecksumKind::Full => value.full = Some(digest.to_string()),
+            }
+            self.changed.lock().unwrap().insert(key);
+        }
+
+        Ok(digest)
+    }
+
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    /// Writes the changed entries to the disk. They are appended to the cache file, unless entries were removed or
+    /// the file grew too large for its entries, in which case the file is replaced atomically
+    pub fn save(&self) -> io::Result<()> {
+        let entries = self.entries.lock().unwrap();
+        let mut changed = self.changed.lock().unwrap();
+        let rewrite = self.rewrite.load(Ordering::Relaxed);
+
+        if changed.is_empty() && !rewrite {
+            return Ok(());
+        }
+
+        if let Some(parent) = self.file.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let file_records = self.file_records.load(Ordering::Relaxed) + changed.len();
+        if rewrite || file_records > COMPACTION_RATIO * entries.len() {
+            // The temporary file is named after the process, so two hunts sharing the cache do not write over each other
+            let temp_file = self.file.with_extension(format!("jsonl.{}.tmp", std::process::id()));
+            let written = File::create(&temp_file).and_then(|x| {
+                let mut writer = BufWriter::new(x);
+                for (key, value) in entries.iter() {
+                    write_record(&mut writer, key, value)?;
+                }
+                writer.flush()
+            });
+
+            written.and_then(|_| fs::rename(&temp_file, &self.file)).inspect_err(|_| {
+                let _ = fs::remove_file(&temp_file);
+            })?;
+            self.file_records.store(entries.len(), Ordering::Relaxed);
+        } else {
+            let mut writer = BufWriter::new(File::options().create(true).append(true).open(&self.file)?);
+            for key in changed.iter() {
+                if let Some(value) = entries.get(key) {
+                    write_record(&mut writer, key, value)?;
+                }
+            }
+            writer.flush()?;
+            self.file_records.store(file_records, Ordering::Relaxed);
+        }
+
+        changed.clear();
+        self.rewrite.store(false, Ordering::Relaxed);
+
+        Ok(())
+    }
+
+    /// Removes the entries whose file no longer exists or has changed since it was hashed.
+    /// Returns the number of entries removed
+    pub fn prune(&self) -> usize {
+        let mut entries = self.entries.lock().unwrap();
+        let before = entries.len();
+
+        entries.retain(|key, value| cache_key(&value.path, key.algorithm) == Some(*key));
+
+        let removed = before - entries.len();
+        if removed > 0 {
+            self.rewrite.store(true, Ordering::Relaxed);
+        }
+        removed
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let entries = self.entries.lock().unwrap();
+        let mut entries_by_algorithm: HashMap<HashAlgorithm, usize> = HashMap::new();
+
+        for key in entries.keys() {
+            *entries_by_algorithm.entry(key.algorithm).or_default() += 1;
+        }
+
+        let mut entries_by_algorithm: Vec<(HashAlgorithm, usize)> = entries_by_algorithm.into_iter().collect();
+        entries_by_algorithm.sort_by_key(|x| x.0.to_string());
+
+        CacheStats {
+            file: self.file.clone(),
+            file_size: fs::metadata(&self.file).map(|x| x.len()).unwrap_or_default(),
+            entries: entries.len(),
+            partial_checksums: entries.values().filter(|x| x.partial.is_some()).count(),
+            full_checksums: entries.values().filter(|x| x.full.is_some()).count(),
+            entries_by_algorithm,
+        }
+    }
+}
+
+fn write_record(writer: &mut impl Write, key: &CacheKey, value: &CacheValue) -> io::Result<()> {
+    let record = CacheRecord {
+        key: *key,
+        value: value.clone(),
+    };
+    serde_json::to_writer(&mut *writer, &record).map_err(io::Error::other)?;
+    writer.write_all(b"\n")
+}
+
+/// Builds the cache key from the current metadata of the file.
+/// Returns None when the file cannot be identified by its device and inode
+pub fn cache_key(path: &Path, algorithm: HashAlgorithm) -> Option<CacheKey> {
+    let metadata = fs::metadata(path).ok()?;
+    let (dev, ino) = file_identity(&metadata)?;
+
+    Some(CacheKey {
+        dev,
+        ino,
+        size: metadata.len(),
+        mtime_ns: mtime_ns(&metadata),
+        algorithm,
+    })
+}
+
+#[cfg(target_os = "linux")]
+fn mtime_ns(metadata: &fs::Metadata) -> i64 {
+    metadata.mtime() * 1_000_000_000 + metadata.mtime_nsec()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn mtime_ns(metadata: &fs::Metadata) -> i64 {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|x| x.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|x| x.as_nanos() as i64)
+        .unwrap_or_default()
+}
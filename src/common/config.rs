@@ -1,8 +1,10 @@
 // Copyright (c) 2024 Venkatesh Omkaram
 
-use std::fmt;
+use std::{fmt, str::FromStr, time::Duration};
 
 use clap::Parser;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
 
 #[derive(clap::Args, Debug, Clone)]
 #[command(disable_version_flag = true)]
@@ -11,20 +13,94 @@ pub struct Delete {}
 #[derive(clap::Args, Debug, Clone)]
 #[command(disable_version_flag = true)]
 pub struct HunterOptions {
-    /// Pass the Source Directory (This is the directory under which will be looking for the identical files (aka 'Clones')
-    pub source_dir: String,
+    /// Pass one or more Source Directories (These are the directories under which will be looking for the identical files (aka 'Clones')
+    #[clap(required = true)]
+    pub source_dirs: Vec<String>,
+    /// Pass a Reference Directory. Only the clones which have a copy under a reference directory are reported, and the
+    /// files under it are never deleted. Can be used more than once
+    #[clap(long = "reference")]
+    pub references: Vec<String>,
     /// Pass the Maximum Depth of directories to scan
     #[clap(short, long, default_value_t = 10)]
     pub max_depth: usize,
     /// Use this option if you don't wish to specify a max_depth.
     #[clap(long, default_value_t = false)]
     pub no_max_depth: bool,
-    /// Hunt for clones by performing partial file checksums.
+    /// Hunt for clones by performing checksums. The files which match on a partial checksum are verified with a full checksum.
     #[clap(short, long, default_value_t = false)]
     pub checksum: bool,
-    /// Find clones for a specific file type. Example -e pdf or -e pdf,txt,mp4
+    /// Hunt for identical directory trees instead of identical files (requires `-c`)
+    #[clap(long, default_value_t = false, requires = "checksum")]
+    pub dirs: bool,
+    /// Hunt for text files whose content is at least this similar (from 0 to 1, for example 0.9) instead of identical files
+    #[clap(long, conflicts_with_all = ["checksum", "dirs", "unique"])]
+    pub similar: Option<f64>,
+    /// How the similarity of two text files is measured (requires `--similar`)
+    #[clap(long, value_enum, default_value_t = SimilarityMetric::Levenshtein, requires = "similar")]
+    pub similarity: SimilarityMetric,
+    /// Hunt for files whose names are at least this similar (from 0 to 1, for example 0.9), such as renamed copies
+    #[clap(long, conflicts_with_all = ["checksum", "dirs", "unique", "similar"])]
+    pub similar_names: Option<f64>,
+    /// What the files with similar names must additionally share (requires `--similar-names`)
+    #[clap(long, value_enum, default_value_t = NameCheck::None, requires = "similar_names")]
+    pub name_check: NameCheck,
+    /// Hunt for images which look the same, even when they were saved in another format, quality or size
+    #[clap(long, default_value_t = false, conflicts_with_all = ["checksum", "dirs", "unique", "similar", "similar_names"])]
+    pub images: bool,
+    /// Perceptual hash used to compare the images (requires `--images`)
+    #[clap(long, value_enum, default_value_t = ImageHash::PHash, requires = "images")]
+    pub image_hash: ImageHash,
+    /// Maximum number of bits in which the hashes of two images may differ (requires `--images`)
+    #[clap(long, default_value_t = 5, requires = "images")]
+    pub image_distance: u32,
+    /// Hunt for files which share at least this fraction of their content (from 0 to 1, for example 0.5), found by splitting them into content-defined chunks
+    #[clap(long, conflicts_with_all = ["checksum", "dirs", "unique", "similar", "similar_names", "images"])]
+    pub overlap: Option<f64>,
+    /// Average size of the chunks the files are split into, from "256 B" to "4 MiB" (requires `--overlap`)
+    #[clap(long, default_value = "64 KiB", requires = "overlap")]
+    pub chunk_size: String,
+    /// The file properties which clones must share when no checksum is made, separated by commas. Any of name, iname
+    /// (the name ignoring case), size, extension and mtime, which takes a tolerance like mtime~2s
+    #[clap(long = "match", value_delimiter = ',', default_value = "name,size,mtime", conflicts_with_all = ["checksum", "similar", "similar_names", "images", "overlap"])]
+    pub match_on: Vec<MatchCriterion>,
+    /// Report the files which have no clone instead of the clones
+    #[clap(long, default_value_t = false, conflicts_with = "dirs")]
+    pub unique: bool,
+    /// Also hunt inside zip, tar and tar.gz archives. Their members are grouped with the regular files
+    #[clap(long, default_value_t = false, requires = "checksum", conflicts_with = "dirs")]
+    pub scan_archives: bool,
+    /// Additionally compare the clones found by the checksum byte by byte (requires `-c`)
+    #[clap(long, default_value_t = false, requires = "checksum")]
+    pub byte_compare: bool,
+    /// Hash algorithm used for the checksums (requires `-c`)
+    #[clap(long, value_enum, default_value_t = HashAlgorithm::Md5, requires = "checksum")]
+    pub hash: HashAlgorithm,
+    /// How the hardlinks of the same file are treated
+    #[clap(long, value_enum, default_value_t = HardlinkMode::Collapse)]
+    pub hardlinks: HardlinkMode,
+    /// Do not read or update the on-disk checksum cache
+    #[clap(long, default_value_t = false)]
+    pub no_cache: bool,
+    /// Find clones for a specific file type, ignoring the case of the extension. Example -e pdf or -e pdf,txt,mp4
     #[clap(short, long)]
     pub extension: Option<String>,
+    /// Only hunt the files whose content is of these types, separated by commas. A type is a kind (app, archive, audio,
+    /// book, document, font, image, text, video), an extension like pdf or a MIME type like video/mp4
+    #[clap(long = "type", value_delimiter = ',')]
+    pub types: Vec<String>,
+    /// Only hunt the files matching this glob pattern, like "*.jpg" or "photos/**". Can be used more than once
+    #[clap(long)]
+    pub include: Vec<String>,
+    /// Leave out the files and directories matching this glob pattern, like "node_modules" or "*.tmp". Excluded
+    /// directories are not walked at all. Can be used more than once
+    #[clap(long)]
+    pub exclude: Vec<String>,
+    /// Leave out the files and directories whose full path matches this regular expression. Can be used more than once
+    #[clap(long)]
+    pub exclude_regex: Vec<String>,
+    /// Do not honour the .gitignore, .ignore and .clonehunterignore files found in the directories
+    #[clap(long, default_value_t = false)]
+    pub no_ignore: bool,
     /// Sorts the output.
     #[clap(short, long, value_enum, default_value_t = SortBy::FileType)]
     pub sort_by: SortBy,
@@ -36,11 +112,35 @@ pub struct HunterOptions {
     /// (Additionally you can also use "KiB", "MiB", "GiB", "KB", "MB", "GB". For example: "150 KiB" with quotes)
     #[clap(short = 'j', long)]
     pub max: Option<String>,
-    /// Targets minimum file sizes in bytes (not to be used with --max)
+    /// Targets minimum file sizes in bytes. Can be used with --max for a range
     /// 
     /// (Additionally you can also use "KiB", "MiB", "GiB", "KB", "MB", "GB". For example: "13 MiB" with quotes)
     #[clap(short = 'l', long)]
     pub min: Option<String>,
+    /// Only hunt the files whose timestamp is newer than this. Either a duration before now like "7d" or "12h", or a
+    /// local date like "2024-05-01" or "2024-05-01 18:30:00"
+    #[clap(long)]
+    pub newer_than: Option<String>,
+    /// Only hunt the files whose timestamp is older than this. Either a duration before now like "30d", or a local date
+    /// like "2024-05-01" or "2024-05-01 18:30:00"
+    #[clap(long)]
+    pub older_than: Option<String>,
+    /// Only hunt the files whose timestamp is newer than the same timestamp of this reference file
+    #[clap(long)]
+    pub changed_since: Option<String>,
+    /// Only hunt the files owned by this user, given by its name or its uid (Linux only)
+    #[clap(long)]
+    pub owner: Option<String>,
+    /// Only hunt the files belonging to this group, given by its name or its gid (Linux only)
+    #[clap(long)]
+    pub group: Option<String>,
+    /// Only hunt the files with these permission bits, in octal like the -perm test of find: "644" for exactly these
+    /// bits, "-220" for all of them and "/022" for any of them (Linux only)
+    #[clap(long, allow_hyphen_values = true)]
+    pub perm: Option<PermissionFilter>,
+    /// The timestamp compared by --newer-than, --older-than and --changed-since
+    #[clap(long, value_enum, default_value_t = TimeField::Mtime)]
+    pub time_field: TimeField,
     /// Write the output to a file using various styles (requires `-f`)
     #[clap(short = 'u', long, requires = "output_file")]
     pub output_style: Option<OutputStyle>,
@@ -58,12 +158,83 @@ pub struct DeleteOptions {
     /// Dry run (test to see if the deletion works without deleting anything)
     #[clap(long, default_value_t = false)]
     pub dry_run: bool,
+    /// Choose which file of a group is kept: oldest, newest, shortest-path, longest-path, first-root or
+    /// path-matches=<regex>. Can be used more than once, and each policy breaks the ties left by the ones before it.
+    /// The last file of a group in the report is kept when the policies leave a tie
+    #[clap(long)]
+    pub keep: Vec<KeepPolicy>,
+    /// Move the duplicates to the trash (the freedesktop.org trash on Linux) instead of deleting them for good, so they
+    /// can be restored from a file manager. This is the default when run from a terminal
+    #[clap(long, default_value_t = false, conflicts_with = "no_trash")]
+    pub trash: bool,
+    /// Delete the duplicates for good, even when run from a terminal
+    #[clap(long, default_value_t = false)]
+    pub no_trash: bool,
+    /// Move the duplicates into a tree under this directory which mirrors their paths, along with a manifest which
+    /// `undo` restores them from
+    #[clap(long, conflicts_with = "trash")]
+    pub quarantine: Option<String>,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+#[command(disable_version_flag = true)]
+pub struct LinkOptions {
+    /// Provide the JSON input file path which you generated using the `hunt -u json -f report_file.json` command
+    #[clap(short, long)]
+    pub input_file: String,
+    /// Replace the duplicates with hardlinks to the file which is kept. This is the only kind of link for now
+    #[clap(long, required = true)]
+    pub hard: bool,
+    /// Dry run (test to see if the linking works without changing anything)
+    #[clap(long, default_value_t = false)]
+    pub dry_run: bool,
+    /// Choose which file of a group the others are linked to: oldest, newest, shortest-path, longest-path, first-root
+    /// or path-matches=<regex>. Can be used more than once, like with `delete`
+    #[clap(long)]
+    pub keep: Vec<KeepPolicy>,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+#[command(disable_version_flag = true)]
+pub struct UndoOptions {
+    /// The manifest written by `delete --quarantine`
+    pub manifest: String,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+#[command(disable_version_flag = true)]
+pub struct PurgeOptions {
+    /// The quarantine directory passed to `delete --quarantine`
+    pub quarantine_dir: String,
+    /// Only purge the files quarantined before this point in time, given as a duration before now like "30d" or as a
+    /// date like "2024-05-01". Everything is purged without it
+    #[clap(long)]
+    pub older_than: Option<String>,
+    /// Dry run (test to see if the purge works without deleting anything)
+    #[clap(long, default_value_t = false)]
+    pub dry_run: bool,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+#[command(disable_version_flag = true)]
+pub struct CacheOptions {
+    #[command(subcommand)]
+    pub action: CacheAction,
+}
+
+#[derive(clap::Subcommand, Debug, Clone)]
+pub enum CacheAction {
+    /// Show what the checksum cache holds
+    Stats,
+    /// Remove the cached checksums of files which were deleted or changed
+    Prune,
 }
 
 /// SortBy User Option
 #[derive(clap::ValueEnum, Clone, Copy, Debug)]
 pub enum SortBy {
     FileType,
+    DetectedType,
     FileSize,
     Both,
 }
@@ -83,13 +254,206 @@ pub enum OutputStyle {
     JSON,
 }
 
+/// Similarity User Option
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub enum SimilarityMetric {
+    /// One minus the line edit distance divided by the line count of the longer file
+    Levenshtein,
+    /// Jaro-Winkler similarity over the lines of the files
+    JaroWinkler,
+}
+
+/// NameCheck User Option
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NameCheck {
+    /// The names alone decide
+    None,
+    /// The files must also have the same size
+    Size,
+    /// The files must also have the same size and partial checksum
+    PartialHash,
+}
+
+/// ImageHash User Option
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub enum ImageHash {
+    /// Average hash: each pixel of an 8x8 thumbnail is compared with the mean brightness
+    #[value(name = "ahash")]
+    AHash,
+    /// Difference hash: each pixel of a 9x8 thumbnail is compared with its right neighbour
+    #[value(name = "dhash")]
+    DHash,
+    /// Perceptual hash: the low frequencies of the cosine transform of a 32x32 thumbnail are compared with their median
+    #[value(name = "phash")]
+    PHash,
+}
+
+/// Match User Option
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MatchCriterion {
+    /// The file name
+    Name,
+    /// The file name, ignoring the case of its letters
+    IName,
+    /// The file size
+    Size,
+    /// The file extension, ignoring the case of its letters
+    Extension,
+    /// The modified time, which may differ by up to the tolerance
+    Mtime(Duration),
+}
+
+impl FromStr for MatchCriterion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "name" => Ok(MatchCriterion::Name),
+            "iname" => Ok(MatchCriterion::IName),
+            "size" => Ok(MatchCriterion::Size),
+            "extension" => Ok(MatchCriterion::Extension),
+            "mtime" => Ok(MatchCriterion::Mtime(Duration::ZERO)),
+            other => match other.strip_prefix("mtime~") {
+                Some(tolerance) => humantime::parse_duration(tolerance)
+                    .map(MatchCriterion::Mtime)
+                    .map_err(|e| format!("invalid mtime tolerance '{}': {}", tolerance, e)),
+                None => Err(format!(
+                    "unknown criterion '{}', expected one of name, iname, size, extension, mtime or mtime~<tolerance>",
+                    other
+                )),
+            },
+        }
+    }
+}
+
+/// Perm User Option
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PermissionFilter {
+    /// The permission bits are exactly these
+    Exact(u32),
+    /// All of these permission bits are set
+    All(u32),
+    /// Any of these permission bits is set. No bits at all match every file
+    Any(u32),
+}
+
+impl PermissionFilter {
+    /// Whether the permission bits of a file mode pass the filter
+    pub fn matches(&self, mode: u32) -> bool {
+        let mode = mode & 0o7777;
+        match *self {
+            PermissionFilter::Exact(bits) => mode == bits,
+            PermissionFilter::All(bits) => mode & bits == bits,
+            PermissionFilter::Any(bits) => bits == 0 || mode & bits != 0,
+        }
+    }
+}
+
+impl FromStr for PermissionFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (kind, bits): (fn(u32) -> PermissionFilter, &str) = match s.chars().next() {
+            Some('-') => (PermissionFilter::All, &s[1..]),
+            Some('/') => (PermissionFilter::Any, &s[1..]),
+            _ => (PermissionFilter::Exact, s),
+        };
+        if bits.is_empty() || bits.len() > 4 || !bits.chars().all(|c| ('0'..='7').contains(&c)) {
+            return Err(format!("invalid permissions '{}', expected octal bits like 644, -220 or /022", s));
+        }
+        u32::from_str_radix(bits, 8).map(kind).map_err(|e| e.to_string())
+    }
+}
+
+/// Keep User Option
+#[derive(Clone, Debug)]
+pub enum KeepPolicy {
+    /// The file modified the longest time ago
+    Oldest,
+    /// The file modified most recently
+    Newest,
+    /// The file with the fewest characters in its path
+    ShortestPath,
+    /// The file with the most characters in its path
+    LongestPath,
+    /// The file under the source directory which was passed first to the hunt
+    FirstRoot,
+    /// The file whose path matches the regular expression
+    PathMatches(Regex),
+}
+
+impl FromStr for KeepPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "oldest" => Ok(KeepPolicy::Oldest),
+            "newest" => Ok(KeepPolicy::Newest),
+            "shortest-path" => Ok(KeepPolicy::ShortestPath),
+            "longest-path" => Ok(KeepPolicy::LongestPath),
+            "first-root" => Ok(KeepPolicy::FirstRoot),
+            other => match other.strip_prefix("path-matches=") {
+                Some(pattern) => Regex::new(pattern)
+                    .map(KeepPolicy::PathMatches)
+                    .map_err(|e| format!("invalid regular expression '{}': {}", pattern, e)),
+                None => Err(format!(
+                    "unknown policy '{}', expected one of oldest, newest, shortest-path, longest-path, first-root or path-matches=<regex>",
+                    other
+                )),
+            },
+        }
+    }
+}
+
+/// TimeField User Option
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimeField {
+    /// The time the content was last modified
+    Mtime,
+    /// The time the content or the metadata was last changed (Linux only)
+    Ctime,
+    /// The time the file was created, when the file system records it
+    Btime,
+}
+
+/// Hardlinks User Option
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HardlinkMode {
+    /// Hash each file once and show its other hardlinks under it in the report
+    Collapse,
+    /// Treat every hardlink as a separate file, which can be reported as a clone
+    Duplicates,
+    /// Hash each file once and leave its other hardlinks out of the report
+    Ignore,
+}
+
+/// Hash algorithm User Option
+#[derive(clap::ValueEnum, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgorithm {
+    Md5,
+    Sha256,
+    Blake3,
+    /// The 128 bit variant of XXH3
+    Xxh3,
+}
+
 #[derive(clap::Subcommand, Debug, Clone)]
 //#[command(disable_version_flag = true)]
 pub enum Command {
     /// Search for clones (duplicates)
-    Hunt(HunterOptions),
+    Hunt(Box<HunterOptions>),
     /// Delete the extracted clones
     Delete(DeleteOptions),
+    /// Replace the extracted clones with links to a single copy
+    Link(LinkOptions),
+    /// Restore the files which `delete --quarantine` moved away
+    Undo(UndoOptions),
+    /// Delete for good the files in a quarantine directory
+    Purge(PurgeOptions),
+    /// Manage the checksum cache used by `hunt -c`
+    Cache(CacheOptions),
 }
 
 #[derive(Parser, Clone)]
@@ -108,8 +472,8 @@ pub struct Args {
 impl fmt::Display for OrderBy {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            OrderBy::Asc => write!(f, "{}", "Asc"),
-            OrderBy::Desc => write!(f, "{}", "Desc"),
+            OrderBy::Asc => write!(f, "Asc"),
+            OrderBy::Desc => write!(f, "Desc"),
         }
     }
 }
@@ -117,8 +481,133 @@ impl fmt::Display for OrderBy {
 impl fmt::Display for OutputStyle {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            OutputStyle::Default => write!(f, "{}", "Default"),
-            OutputStyle::JSON => write!(f, "{}", "JSON"),
+            OutputStyle::Default => write!(f, "Default"),
+            OutputStyle::JSON => write!(f, "JSON"),
+        }
+    }
+}
+
+impl fmt::Display for HashAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HashAlgorithm::Md5 => write!(f, "md5"),
+            HashAlgorithm::Sha256 => write!(f, "sha256"),
+            HashAlgorithm::Blake3 => write!(f, "blake3"),
+            HashAlgorithm::Xxh3 => write!(f, "xxh3"),
+        }
+    }
+}
+
+impl fmt::Display for HardlinkMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HardlinkMode::Collapse => write!(f, "Collapse"),
+            HardlinkMode::Duplicates => write!(f, "Duplicates"),
+            HardlinkMode::Ignore => write!(f, "Ignore"),
+        }
+    }
+}
+
+impl fmt::Display for SimilarityMetric {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SimilarityMetric::Levenshtein => write!(f, "levenshtein"),
+            SimilarityMetric::JaroWinkler => write!(f, "jaro-winkler"),
+        }
+    }
+}
+
+impl fmt::Display for NameCheck {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NameCheck::None => write!(f, "None"),
+            NameCheck::Size => write!(f, "Size"),
+            NameCheck::PartialHash => write!(f, "Partial hash"),
+        }
+    }
+}
+
+impl fmt::Display for PermissionFilter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PermissionFilter::Exact(bits) => write!(f, "{:o}", bits),
+            PermissionFilter::All(bits) => write!(f, "-{:o}", bits),
+            PermissionFilter::Any(bits) => write!(f, "/{:o}", bits),
         }
     }
 }
+
+impl fmt::Display for MatchCriterion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MatchCriterion::Name => write!(f, "name"),
+            MatchCriterion::IName => write!(f, "iname"),
+            MatchCriterion::Size => write!(f, "size"),
+            MatchCriterion::Extension => write!(f, "extension"),
+            MatchCriterion::Mtime(tolerance) if tolerance.is_zero() => write!(f, "mtime"),
+            MatchCriterion::Mtime(tolerance) => write!(f, "mtime~{}", humantime::format_duration(*tolerance)),
+        }
+    }
+}
+
+impl fmt::Display for KeepPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            KeepPolicy::Oldest => write!(f, "oldest"),
+            KeepPolicy::Newest => write!(f, "newest"),
+            KeepPolicy::ShortestPath => write!(f, "shortest-path"),
+            KeepPolicy::LongestPath => write!(f, "longest-path"),
+            KeepPolicy::FirstRoot => write!(f, "first-root"),
+            KeepPolicy::PathMatches(pattern) => write!(f, "path-matches={}", pattern),
+        }
+    }
+}
+
+impl fmt::Display for TimeField {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TimeField::Mtime => write!(f, "mtime"),
+            TimeField::Ctime => write!(f, "ctime"),
+            TimeField::Btime => write!(f, "btime"),
+        }
+    }
+}
+
+impl fmt::Display for ImageHash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ImageHash::AHash => write!(f, "ahash"),
+            ImageHash::DHash => write!(f, "dhash"),
+            ImageHash::PHash => write!(f, "phash"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn permissions_parse_like_find() {
+        assert_eq!("644".parse(), Ok(PermissionFilter::Exact(0o644)));
+        assert_eq!("-220".parse(), Ok(PermissionFilter::All(0o220)));
+        assert_eq!("/0022".parse(), Ok(PermissionFilter::Any(0o22)));
+        for invalid in ["", "-", "/", "648", "+644", "-+644", "17777", "rw"] {
+            assert!(invalid.parse::<PermissionFilter>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn permissions_match_the_mode_bits() {
+        // The file type bits of a regular file are left out
+        let mode = 0o100644;
+
+        assert!(PermissionFilter::Exact(0o644).matches(mode));
+        assert!(!PermissionFilter::Exact(0o600).matches(mode));
+        assert!(PermissionFilter::All(0o604).matches(mode));
+        assert!(!PermissionFilter::All(0o606).matches(mode));
+        assert!(PermissionFilter::Any(0o022).matches(0o100620));
+        assert!(!PermissionFilter::Any(0o022).matches(mode));
+        assert!(PermissionFilter::Any(0).matches(mode));
+    }
+}
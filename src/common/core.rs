@@ -1,21 +1,21 @@
 // Copyright (c) 2024 Venkatesh Omkaram
 
-use chrono::{DateTime, Local};
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, NaiveTime, TimeZone};
 use clap::builder::OsStr;
 use colored::Colorize;
-use hashbrown::HashMap;
+use hashbrown::{HashMap, HashSet};
 use human_bytes::human_bytes;
 use indicatif::ProgressBar;
 use jwalk::WalkDir;
 use lazy_static::lazy_static;
-use rayon::iter::{ParallelBridge, ParallelIterator};
+use rayon::iter::{IntoParallelRefIterator, ParallelBridge, ParallelIterator};
 use serde::{Deserialize, Serialize};
 use std::{
-    fmt::Debug,
+    collections::BTreeMap,
+    fmt,
     fs::{self, File},
-    hash::Hash,
     io::{stdin, stdout, BufWriter, Write},
-    path::PathBuf,
+    path::{Path, PathBuf},
     rc::Rc,
     sync::{Arc, Mutex},
     time::SystemTime,
@@ -29,7 +29,11 @@ use std::os::unix::fs::MetadataExt;
 use std::os::windows::fs::MetadataExt;
 
 use crate::common::{
-    config::{OrderBy, OutputStyle, SortBy},
+    archive::is_member,
+    filetype::{detect_type, type_mismatch, FileType},
+    filter::PathFilter,
+    config::{HashAlgorithm, MatchCriterion, OrderBy, OutputStyle, PermissionFilter, SortBy, TimeField},
+    hasher::Digest,
     trait_defs,
 };
 
@@ -82,12 +86,36 @@ macro_rules! logger {
     };
 }
 
-/// A Struct which can help generate a Hash on its fields
+/// A Struct which can help generate a Hash on the file properties chosen with `--match`. The modified time is left out,
+/// as it is compared apart with its tolerance
 #[derive(Hash)]
-pub struct FileMetaData<'a> {
-    pub file_name: &'a str,
-    pub modified_date: SystemTime,
-    pub file_size: u64,
+pub struct FileMetaData {
+    pub file_name: Option<String>,
+    pub extension: Option<String>,
+    pub file_size: Option<u64>,
+}
+
+impl FileMetaData {
+    pub fn new(path: &Path, file_size: u64, criteria: &[MatchCriterion]) -> Self {
+        let lowercase = |x: &std::ffi::OsStr| x.to_string_lossy().to_lowercase();
+        let mut metadata = FileMetaData {
+            file_name: None,
+            extension: None,
+            file_size: None,
+        };
+
+        for criterion in criteria {
+            match criterion {
+                MatchCriterion::Name => metadata.file_name = path.file_name().map(|x| x.to_string_lossy().into_owned()),
+                MatchCriterion::IName => metadata.file_name = path.file_name().map(lowercase),
+                MatchCriterion::Extension => metadata.extension = Some(path.extension().map(lowercase).unwrap_or_default()),
+                MatchCriterion::Size => metadata.file_size = Some(file_size),
+                MatchCriterion::Mtime(_) => {}
+            }
+        }
+
+        metadata
+    }
 }
 
 /// Struct which holds SortBy and OrderBy User Options
@@ -98,6 +126,88 @@ pub struct PrinterConfig {
     pub file: Option<File>,
     pub sort_order: SortOrder,
     pub output_style: OutputStyle,
+    /// The hash algorithm used for the checksums, if any. This is recorded in the JSON report
+    pub hash_algorithm: Option<HashAlgorithm>,
+    /// The canonical source directories which were walked. Each file is labelled with its directory when there are several
+    pub source_dirs: Vec<PathBuf>,
+    /// Print the files which have no clone. Every group then holds a single file
+    pub unique: bool,
+    /// The bytes a deduplicating file system would save, when the files were split into chunks. This is recorded in the JSON report
+    pub dedupable_bytes: Option<u64>,
+    /// The file properties the clones were matched on, when no checksum was made. This is recorded in the JSON report
+    pub match_criteria: Vec<MatchCriterion>,
+    /// Flag the files whose extension does not fit the type detected from their content. This reads the first bytes of
+    /// every file in the report, so it is only set when the hunt reads the content of the files anyway
+    pub flag_type_mismatches: bool,
+}
+
+/// Version of the JSON report format. Version 1 is the bare list of groups written by the older versions, and version 2
+/// is the report object
+pub const REPORT_VERSION: u32 = 2;
+
+fn report_version() -> u32 {
+    REPORT_VERSION
+}
+
+/// JSON report which is written by the hunt command and consumed by the delete command
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PrinterJSONReport {
+    /// The version of the report format, so the readers of the report can tell its shape
+    #[serde(default = "report_version")]
+    pub version: u32,
+    /// Reports generated by older versions are always clone reports
+    #[serde(default)]
+    pub kind: ReportKind,
+    /// Absent when the clones were found using the file metadata
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash_algorithm: Option<HashAlgorithm>,
+    /// The source directories which were hunted
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub source_dirs: Vec<String>,
+    /// The file properties the clones were matched on. Absent when the clones were found using a checksum
+    #[serde(rename = "match", default, skip_serializing_if = "Vec::is_empty")]
+    pub match_criteria: Vec<String>,
+    /// How many files each stage of the checksum pipeline eliminated
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub stages: Vec<StageStats>,
+    /// Bytes which can be freed by keeping a single copy of each group
+    #[serde(default)]
+    pub reclaimable_bytes: u64,
+    /// Bytes a deduplicating file system would save at the chunk level, when the files were split into chunks
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dedupable_bytes: Option<u64>,
+    /// The clones broken down by the user owning them
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub owners: Vec<OwnerStats>,
+    pub duplicate_groups: Vec<PrinterJSONObject>,
+}
+
+/// Reports generated by older versions are a bare list of groups
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+pub enum PrinterJSONInput {
+    Report(PrinterJSONReport),
+    Legacy(Vec<PrinterJSONObject>),
+}
+
+impl From<PrinterJSONInput> for PrinterJSONReport {
+    fn from(input: PrinterJSONInput) -> Self {
+        match input {
+            PrinterJSONInput::Report(report) => report,
+            PrinterJSONInput::Legacy(duplicate_groups) => PrinterJSONReport {
+                version: 1,
+                kind: ReportKind::Clones,
+                hash_algorithm: None,
+                source_dirs: Vec::new(),
+                match_criteria: Vec::new(),
+                stages: Vec::new(),
+                reclaimable_bytes: 0,
+                dedupable_bytes: None,
+                owners: Vec::new(),
+                duplicate_groups,
+            },
+        }
+    }
 }
 
 /// JSON printer
@@ -107,90 +217,437 @@ pub struct PrinterJSONObject {
     pub duplicate_group_count: usize,
     pub duplicate_group_bytes_each: usize,
     pub duplicate_list: Vec<String>,
+    /// Reports generated by older versions do not carry this field
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub confirmed_by: Option<Stage>,
+    /// Hex encoded content checksum shared by the group
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub checksum: Option<String>,
+    /// The other hardlinks of a file in the `duplicate_list` or the `reference_list`, keyed by that file
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub hardlinks: BTreeMap<String, Vec<String>>,
+    /// The copies found under the reference directories. These are never deleted, and when present
+    /// every file in the `duplicate_list` can be deleted
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub reference_list: Vec<String>,
+    /// The source directory of each file in the `duplicate_list`, written when more than one source directory was hunted
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub roots: BTreeMap<String, String>,
+    /// The pairs of files in a group of similar files along with their similarity score
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub similar_pairs: Vec<PrinterJSONPair>,
+    /// How many bits the perceptual hash of each image differs from the first image of the group
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub distances: BTreeMap<String, u32>,
+    /// The files whose extension is missing or does not fit the type detected from their content, mapped to the MIME
+    /// type of that content
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub type_mismatches: BTreeMap<String, String>,
+    /// The modified time of each file in the `duplicate_list` and the `reference_list` when the report was written,
+    /// in RFC 3339. The `delete` command leaves a file alone when it was modified since
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub modified: BTreeMap<String, String>,
+    /// Set when the files were not matched on their size, so they are not known to be copies. The `delete` and `link`
+    /// commands skip such a group
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub size_not_matched: bool,
+    /// The size of each file in the `duplicate_list` and the `reference_list`, written when the files were not matched
+    /// on their size
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub sizes: BTreeMap<String, u64>,
+}
+
+/// A pair of similar files in the JSON report
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PrinterJSONPair {
+    pub a: String,
+    pub b: String,
+    pub score: f64,
+    /// Bytes of content the two files have in common, when they were split into chunks
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shared_bytes: Option<u64>,
+}
+
+/// What the groups of a JSON report hold
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ReportKind {
+    /// Groups of identical files
+    #[default]
+    Clones,
+    /// Files which have no clone, one per group. Such a report cannot be used to delete files
+    Unique,
+}
+
+/// The stage of the hunt which confirmed a group of files as clones
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Stage {
+    /// Matched on the file properties chosen with `--match`, by default the name, the size and the modified time
+    Metadata,
+    /// Matched on the file size alone. This stage only narrows down the candidates
+    FileSize,
+    /// Matched on the checksum of the first and last few bytes plus the file length
+    PartialHash,
+    /// Matched on the checksum of the entire file content
+    FullHash,
+    /// Matched by comparing the file content byte by byte
+    ByteCompare,
+    /// Matched on the names and checksums of every file and subdirectory in a directory tree
+    DirectoryTree,
+    /// The files are not identical, but their content is similar enough
+    Similarity,
+    /// The files have similar names, which may be backed by their size or partial checksum
+    FileName,
+    /// The images look the same, as their perceptual hashes are close enough
+    PerceptualHash,
+    /// The files share enough of their content-defined chunks
+    ChunkOverlap,
+}
+
+impl Stage {
+    /// Whether the files confirmed by this stage are copies of each other, rather than merely similar files
+    pub fn finds_copies(&self) -> bool {
+        !matches!(self, Stage::Similarity | Stage::FileName | Stage::PerceptualHash | Stage::ChunkOverlap)
+    }
+}
+
+/// Number of files which entered a stage of the hunt and how many of them the stage eliminated
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct StageStats {
+    pub stage: Stage,
+    pub candidates: usize,
+    pub eliminated: usize,
+}
+
+/// A group of files which were found to be identical to each other
+#[derive(Debug, Clone)]
+pub struct CloneGroup {
+    pub bytes_each: u64,
+    pub paths: Vec<PathBuf>,
+    pub confirmed_by: Stage,
+    /// The full content checksum of the group, when one was computed
+    pub digest: Option<Digest>,
+    /// The other hardlinks of a path in the group, keyed by that path
+    pub hardlinks: HashMap<PathBuf, Vec<PathBuf>>,
+    /// The copies which lie under a reference directory. These are not part of `paths`
+    pub references: Vec<PathBuf>,
+    /// The scored pairs which put the files of a group of similar files together
+    pub pairs: Vec<SimilarPair>,
+    /// How many bits the perceptual hash of each image differs from the first image of the group
+    pub distances: HashMap<PathBuf, u32>,
+    /// The size of each file, when the files were not matched on their size. Such a group is not known to hold copies
+    pub sizes: HashMap<PathBuf, u64>,
+}
+
+/// Two files which are similar to each other. The score goes from 0 (nothing in common) to 1 (identical)
+#[derive(Debug, Clone)]
+pub struct SimilarPair {
+    pub a: PathBuf,
+    pub b: PathBuf,
+    pub score: f64,
+    /// Bytes of content the two files have in common, when they were split into chunks
+    pub shared_bytes: Option<u64>,
+}
+
+impl CloneGroup {
+    pub fn new(bytes_each: u64, paths: Vec<PathBuf>, confirmed_by: Stage, digest: Option<Digest>) -> Self {
+        CloneGroup {
+            bytes_each,
+            paths,
+            confirmed_by,
+            digest,
+            hardlinks: HashMap::new(),
+            references: Vec::new(),
+            pairs: Vec::new(),
+            distances: HashMap::new(),
+            sizes: HashMap::new(),
+        }
+    }
+
+    /// Whether the files of the group were matched on their size, so they all have the size `bytes_each`
+    pub fn size_matched(&self) -> bool {
+        self.sizes.is_empty()
+    }
+
+    /// The size of a file of the group
+    pub fn file_bytes(&self, path: &Path) -> u64 {
+        self.sizes.get(path).copied().unwrap_or(self.bytes_each)
+    }
+
+    pub fn first_path(&self) -> &Path {
+        self.paths.first().map(|x| x.as_path()).unwrap_or(Path::new(""))
+    }
+
+    /// Counts the files in the group which occupy their own space on the disk, i.e. the distinct inodes
+    pub fn distinct_files(&self) -> usize {
+        distinct_paths(&self.paths, &mut HashSet::new()).len()
+    }
+
+    /// The files whose space is freed by keeping a single copy of the group. Like the `delete` command, the last file
+    /// is kept, unless the group has reference copies, in which case every file outside the reference directories can
+    /// go. Similar files are not copies, so none of them can go
+    pub fn removable_paths(&self) -> Vec<&PathBuf> {
+        if !self.confirmed_by.finds_copies() {
+            Vec::new()
+        } else if self.references.is_empty() {
+            let mut paths = distinct_paths(&self.paths, &mut HashSet::new());
+            paths.pop();
+            paths
+        } else {
+            let mut identities = HashSet::new();
+            distinct_paths(&self.references, &mut identities);
+            distinct_paths(&self.paths, &mut identities)
+        }
+    }
+
+    /// Bytes which can be freed by keeping a single copy of the group
+    pub fn reclaimable_bytes(&self) -> u64 {
+        self.removable_paths().into_iter().map(|x| self.file_bytes(x)).sum()
+    }
+}
+
+// Keeps the paths whose (device, inode) pair was not seen yet. Paths which cannot be identified are always kept,
+// except the archive members which cannot be deleted
+fn distinct_paths<'a>(paths: &'a [PathBuf], identities: &mut HashSet<(u64, u64)>) -> Vec<&'a PathBuf> {
+    paths
+        .iter()
+        .filter(|x| match fs::metadata(x).ok().as_ref().and_then(file_identity) {
+            Some(identity) => identities.insert(identity),
+            None => !is_member(x),
+        })
+        .collect()
+}
+
+/// The clones owned by a user
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct OwnerStats {
+    pub uid: u32,
+    /// Absent when the user id has no name on this system
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Files of the user which are in a group, references included
+    pub duplicate_files: u64,
+    /// Bytes which keeping a single copy of each group would free on the files of the user
+    pub reclaimable_bytes: u64,
+}
+
+/// Breaks the files of the groups down by the user owning them, from the most reclaimable bytes to the least. Files
+/// whose owner cannot be read, such as archive members, are left out
+pub fn owner_stats(groups: &[CloneGroup]) -> Vec<OwnerStats> {
+    fn stats(by_uid: &mut BTreeMap<u32, OwnerStats>, uid: u32) -> &mut OwnerStats {
+        by_uid.entry(uid).or_insert_with(|| OwnerStats {
+            uid,
+            name: user_name(uid),
+            duplicate_files: 0,
+            reclaimable_bytes: 0,
+        })
+    }
+
+    let mut by_uid: BTreeMap<u32, OwnerStats> = BTreeMap::new();
+    for group in groups {
+        for uid in group.paths.iter().chain(group.references.iter()).filter_map(|x| file_owner(x)) {
+            stats(&mut by_uid, uid).duplicate_files += 1;
+        }
+        for path in group.removable_paths() {
+            if let Some(uid) = file_owner(path) {
+                stats(&mut by_uid, uid).reclaimable_bytes += group.file_bytes(path);
+            }
+        }
+    }
+
+    let mut owners: Vec<OwnerStats> = by_uid.into_values().collect();
+    owners.sort_by_key(|x| std::cmp::Reverse(x.reclaimable_bytes));
+    owners
+}
+
+/// Returns the user id owning a file. Owners are only read on Linux
+pub fn file_owner(path: &Path) -> Option<u32> {
+    #[cfg(target_os = "linux")]
+    {
+        fs::metadata(path).ok().map(|x| x.uid())
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = path;
+        None
+    }
+}
+
+/// Looks up the name of a user id
+pub fn user_name(uid: u32) -> Option<String> {
+    #[cfg(target_os = "linux")]
+    {
+        uzers::get_user_by_uid(uid).map(|x| x.name().to_string_lossy().into_owned())
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = uid;
+        None
+    }
+}
+
+/// Looks up a user by its name or its numeric id
+pub fn user_id(user: &str) -> Option<u32> {
+    #[cfg(target_os = "linux")]
+    {
+        user.parse().ok().or_else(|| uzers::get_user_by_name(user).map(|x| x.uid()))
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        user.parse().ok()
+    }
+}
+
+/// Looks up a group by its name or its numeric id
+pub fn group_id(group: &str) -> Option<u32> {
+    #[cfg(target_os = "linux")]
+    {
+        group.parse().ok().or_else(|| uzers::get_group_by_name(group).map(|x| x.gid()))
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        group.parse().ok()
+    }
+}
+
+/// Totals of the clones which were printed
+pub struct DuplicatesSummary {
+    pub clones_count: u64,
+    pub clones_total_size: u64,
+    /// Hardlinks of the same file are counted only once
+    pub reclaimable_size: u64,
+    /// Bytes a deduplicating file system would save, when the files were split into chunks
+    pub dedupable_size: Option<u64>,
+    /// The clones broken down by the user owning them
+    pub owners: Vec<OwnerStats>,
 }
 
-pub struct WalkConfig<'a> {
-    pub ext: Option<&'a str>,
+pub struct WalkConfig {
+    /// Shared with the threads of the walk, which prune the excluded directories
+    pub filter: Arc<PathFilter>,
     pub max_depth: Option<usize>,
-    pub max_file_size: Option<u64>,
-    pub min_file_size: Option<u64>,
+    /// Every limit must allow a file for it to be hunted
+    pub limits: Vec<FileLimitingFactor>,
 }
 
+/// A limit which a file must be within to be hunted
 pub enum FileLimitingFactor {
-    GreaterThan(usize),
-    LessThan(usize),
-}
-
-pub fn file_list_generator(entry: &PathBuf, wc: &WalkConfig) {
-    if let Some(x) = entry.extension() {
-        if let Some(ext) = wc.ext {
-            let mut vec_ext = ext.split(",");
-            if vec_ext.any(|y| x.eq(y)) {
-                FILE_LIST.lock().unwrap().push(
-                    entry
-                        .to_path_buf()
-                        .canonicalize()
-                        .unwrap_or_else(|_| entry.to_path_buf()),
-                );
-                if cfg!(unix) {
-                    #[cfg(target_os = "linux")]
-                    {
-                        match FILES_SIZE_BYTES.lock().unwrap().as_mut() {
-                            Some(o) => {
-                                *o += match entry.metadata() {
-                                    Ok(p) => p.size(),
-                                    Err(_) => 0,
-                                }
-                            }
-                            None => {}
-                        }
-                    }
-                } else if cfg!(windows) {
-                    #[cfg(target_os = "windows")]
-                    {
-                        match FILES_SIZE_BYTES.lock().unwrap().as_mut() {
-                            Some(o) => {
-                                *o += match entry.metadata() {
-                                    Ok(p) => p.file_size(),
-                                    Err(_) => 0,
-                                }
-                            }
-                            None => {}
-                        }
-                    }
-                }
+    /// The file size is greater than this many bytes
+    GreaterThan(u64),
+    /// The file size is less than this many bytes
+    LessThan(u64),
+    /// The timestamp of the file is after this point in time
+    NewerThan(TimeField, SystemTime),
+    /// The timestamp of the file is before this point in time
+    OlderThan(TimeField, SystemTime),
+    /// The file is owned by this user id. Owners are only read on Linux
+    Owner(u32),
+    /// The file belongs to this group id. Groups are only read on Linux
+    Group(u32),
+    /// The permission bits of the file pass this filter. Permissions are only read on Linux
+    Permissions(PermissionFilter),
+}
+
+impl FileLimitingFactor {
+    /// Whether the file is within the limit. A file whose timestamp cannot be read is not
+    pub fn allows(&self, metadata: &fs::Metadata) -> bool {
+        match self {
+            FileLimitingFactor::GreaterThan(x) => metadata.len() > *x,
+            FileLimitingFactor::LessThan(x) => metadata.len() < *x,
+            FileLimitingFactor::NewerThan(field, time) => file_time(metadata, *field).is_some_and(|x| x > *time),
+            FileLimitingFactor::OlderThan(field, time) => file_time(metadata, *field).is_some_and(|x| x < *time),
+            #[cfg(target_os = "linux")]
+            FileLimitingFactor::Owner(uid) => metadata.uid() == *uid,
+            #[cfg(target_os = "linux")]
+            FileLimitingFactor::Group(gid) => metadata.gid() == *gid,
+            #[cfg(target_os = "linux")]
+            FileLimitingFactor::Permissions(filter) => filter.matches(metadata.mode()),
+            #[cfg(not(target_os = "linux"))]
+            FileLimitingFactor::Owner(_) | FileLimitingFactor::Group(_) | FileLimitingFactor::Permissions(_) => false,
+        }
+    }
+}
+
+/// Reads a timestamp of a file. The status change time is only available on Linux, and the birth time is only
+/// available when the file system records it
+pub fn file_time(metadata: &fs::Metadata, field: TimeField) -> Option<SystemTime> {
+    match field {
+        TimeField::Mtime => metadata.modified().ok(),
+        TimeField::Btime => metadata.created().ok(),
+        TimeField::Ctime => {
+            #[cfg(target_os = "linux")]
+            {
+                let seconds = u64::try_from(metadata.ctime()).ok()?;
+                std::time::UNIX_EPOCH.checked_add(std::time::Duration::new(seconds, metadata.ctime_nsec() as u32))
             }
-        } else {
-            FILE_LIST.lock().unwrap().push(
-                entry
-                    .to_path_buf()
-                    .canonicalize()
-                    .unwrap_or_else(|_| entry.to_path_buf()),
-            );
-            if cfg!(unix) {
-                #[cfg(target_os = "linux")]
-                {
-                    match FILES_SIZE_BYTES.lock().unwrap().as_mut() {
-                        Some(o) => {
-                            *o += match entry.metadata() {
-                                Ok(p) => p.size(),
-                                Err(_) => 0,
-                            }
-                        }
-                        None => {}
+            #[cfg(not(target_os = "linux"))]
+            {
+                None
+            }
+        }
+    }
+}
+
+/// Parses a point in time given either as a duration before now, like "7d" or "12h", or as a local date, like
+/// "2024-05-01" or "2024-05-01 18:30:00"
+pub fn parse_point_in_time(value: &str) -> Result<SystemTime, String> {
+    if let Ok(duration) = humantime::parse_duration(value) {
+        return SystemTime::now()
+            .checked_sub(duration)
+            .ok_or_else(|| format!("The duration {} goes too far back", value));
+    }
+
+    let datetime = NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S")
+        .or_else(|_| NaiveDate::parse_from_str(value, "%Y-%m-%d").map(|x| x.and_time(NaiveTime::MIN)))
+        .map_err(|_| format!("{} is neither a duration like \"7d\" nor a date like \"2024-05-01\"", value))?;
+
+    Local
+        .from_local_datetime(&datetime)
+        .earliest()
+        .map(SystemTime::from)
+        .ok_or_else(|| format!("{} does not exist in the local time zone", value))
+}
+
+/// Returns the device and inode numbers which uniquely identify a file on the disk.
+/// These are not available on Windows, where every path is treated as a separate file
+pub fn file_identity(metadata: &fs::Metadata) -> Option<(u64, u64)> {
+    #[cfg(target_os = "linux")]
+    {
+        Some((metadata.dev(), metadata.ino()))
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = metadata;
+        None
+    }
+}
+
+pub fn file_list_generator(entry: &Path, wc: &WalkConfig) {
+    if wc.filter.accepts_file(entry) {
+        FILE_LIST.lock().unwrap().push(
+            entry
+                .to_path_buf()
+                .canonicalize()
+                .unwrap_or_else(|_| entry.to_path_buf()),
+        );
+        if cfg!(unix) {
+            #[cfg(target_os = "linux")]
+            {
+                if let Some(o) = FILES_SIZE_BYTES.lock().unwrap().as_mut() {
+                    *o += match entry.metadata() {
+                        Ok(p) => p.size(),
+                        Err(_) => 0,
                     }
                 }
-            } else if cfg!(windows) {
-                #[cfg(target_os = "windows")]
-                {
-                    match FILES_SIZE_BYTES.lock().unwrap().as_mut() {
-                        Some(o) => {
-                            *o += match entry.metadata() {
-                                Ok(p) => p.file_size(),
-                                Err(_) => 0,
-                            }
-                        }
-                        None => {}
+            }
+        } else if cfg!(windows) {
+            #[cfg(target_os = "windows")]
+            {
+                if let Some(o) = FILES_SIZE_BYTES.lock().unwrap().as_mut() {
+                    *o += match entry.metadata() {
+                        Ok(p) => p.file_size(),
+                        Err(_) => 0,
                     }
                 }
             }
@@ -213,42 +670,15 @@ where
     } else {
         **pos.lock().unwrap() += 1;
         pb.set_position(**pos.lock().unwrap());
-        let mut actual_file_size = 0;
-        if cfg!(unix) {
-            #[cfg(target_os = "linux")]
-            {
-                actual_file_size = match entry.metadata() {
-                    Ok(p) => p.size(),
-                    Err(_) => 0,
-                }
-            }
-        } else if cfg!(windows) {
-            #[cfg(target_os = "windows")]
-            {
-                actual_file_size = match entry.metadata() {
-                    Ok(p) => p.file_size(),
-                    Err(_) => 0,
-                }
-            }
-        };
 
-        match wc.min_file_size {
-            Some(x) => {
-                if actual_file_size > x {
-                    file_list_generator(&entry, wc);
-                }
-            }
-            None => {
-                match wc.max_file_size {
-                    Some(x) => {
-                        if actual_file_size < x {
-                            file_list_generator(&entry, wc);
-                        }
-                    }
-                    None => file_list_generator(&entry, wc),
-                };
-            }
+        let allowed = match entry.metadata() {
+            Ok(metadata) => wc.limits.iter().all(|x| x.allows(&metadata)),
+            Err(_) => wc.limits.is_empty(),
         };
+
+        if allowed {
+            file_list_generator(&entry, wc);
+        }
     }
 }
 
@@ -259,6 +689,10 @@ pub fn recurse_dirs(item: &PathBuf, wc: &WalkConfig, pb: &ProgressBar, pos: Arc<
     if item.is_dir() {
         if let Ok(paths) = fs::read_dir(item) {
             for path in paths {
+                // Excluded directories are pruned here, so nothing under them is read
+                if path.as_ref().is_ok_and(|x| x.path().is_dir() && wc.filter.excludes_dir(&x.path())) {
+                    continue;
+                }
                 walk_and_recurse_dirs_inner(&path, wc, pb, pos.clone());
                 recurse_dirs(&path.unwrap().path(), wc, pb, pos.clone());
             }
@@ -271,10 +705,15 @@ pub fn recurse_dirs(item: &PathBuf, wc: &WalkConfig, pb: &ProgressBar, pos: Arc<
 /// But uses WalkDir and Rayon to make it fast.
 pub fn walk_dirs(item: &PathBuf, threads: u8, wc: &WalkConfig, pb: &ProgressBar, pos: Arc<Mutex<&mut u64>>) {
     if item.is_dir() {
+        let filter = wc.filter.clone();
         let _: Vec<_> = WalkDir::new(item)
             .skip_hidden(false)
             .max_depth(wc.max_depth.unwrap())
             .parallelism(jwalk::Parallelism::RayonNewPool(threads.into()))
+            // Excluded directories are pruned before the walk reads them
+            .process_read_dir(move |_, _, _, children| {
+                children.retain(|x| x.as_ref().map_or(true, |x| !(x.file_type().is_dir() && filter.excludes_dir(&x.path()))));
+            })
             .into_iter()
             .par_bridge()
             .filter_map(|dir_entry| {
@@ -312,36 +751,49 @@ pub fn log(level: LogLevel, message: &str) {
     }
 }
 
-/// This free standing function helps to display all the duplicate file and their respective groups file sizes.
-/// It filters for duplicate files from the provided arc_vec_paths HashMap, and figures out the file sizes for each
-/// group based on arc_capacities HashMap. Once the filtering and printing to screen is completed, it return the total number of duplicate records count
-pub fn print_duplicates<T, U, K>(
-    arc_vec_paths: &mut Arc<Mutex<HashMap<K, T>>>,
-    arc_capacities: &Arc<Mutex<HashMap<K, U>>>,
-    print_config: PrinterConfig,
-) -> (u64, u64)
-where
-    T: IntoIterator + ExactSize + Clone + Paths,
-    <T as IntoIterator>::Item: Debug + Displayer,
-    U: AsF64,
-    K: Eq + Hash,
-{
+/// This free standing function helps to display all the duplicate file groups and their respective file sizes.
+/// It filters out the groups with a single file (or keeps only those when printing the unique files), sorts the remaining groups using the SortOrder and prints them
+/// either to the screen or to a file. The stages statistics are only recorded in the JSON report, as they were already
+/// logged while hunting. Once the printing is completed, it returns the total number of duplicate records count,
+/// the total file size of the groups and the space which can be reclaimed
+pub fn print_duplicates(groups: Vec<CloneGroup>, stages: Vec<StageStats>, print_config: PrinterConfig) -> DuplicatesSummary {
     let mut duplicates_count: u64 = 0;
     let mut duplicates_total_size: u64 = 0;
-    let mut arc_vec_paths = arc_vec_paths.lock().unwrap();
+    let mut reclaimable_size: u64 = 0;
 
-    let arc_capacities = arc_capacities.lock().unwrap();
+    let mut filtered_duplicates_result: Vec<CloneGroup> =
+        groups
+            .into_iter()
+            .filter(|x| if print_config.unique { x.paths.len() == 1 } else { x.paths.len() + x.references.len() > 1 })
+            .collect();
+
+    filtered_duplicates_result.iter().for_each(|x| {
+        duplicates_count += (x.paths.len() + x.references.len()) as u64;
+        duplicates_total_size += x.bytes_each;
+        reclaimable_size += x.reclaimable_bytes();
+    });
 
-    arc_vec_paths
-        .iter_mut()
-        .filter(|x| x.1.len() > 1)
-        .for_each(|x| duplicates_count += x.1.len() as u64);
+    let owners = if print_config.unique { Vec::new() } else { owner_stats(&filtered_duplicates_result) };
 
-    let filtered_duplicates_result = arc_vec_paths.iter_mut().filter(|x| x.1.len() > 1);
-    let mut filtered_duplicates_result: Vec<(&K, &T)> = filtered_duplicates_result
-        .map(|(&ref k, v)| (k, &*v))
+    // The files whose extension does not fit their content are flagged in the report. Directories have no type
+    let mismatches: HashMap<PathBuf, FileType> = filtered_duplicates_result
+        .iter()
+        .filter(|x| print_config.flag_type_mismatches && x.confirmed_by != Stage::DirectoryTree)
+        .flat_map(|x| x.paths.iter().chain(x.references.iter()))
+        .collect::<Vec<_>>()
+        .par_iter()
+        .filter_map(|x| type_mismatch(x).map(|file_type| (x.to_path_buf(), file_type)))
+        .collect::<Vec<_>>()
+        .into_iter()
         .collect();
 
+    if !mismatches.is_empty() {
+        log(
+            LogLevel::INFO,
+            format!("Found {} file(s) whose extension does not fit their content", mismatches.len()).as_str(),
+        );
+    }
+
     let sort_by = print_config.sort_order.0;
     let order_by = print_config.sort_order.1;
 
@@ -349,40 +801,39 @@ where
         SortBy::FileType => {
             // Sorts the duplicates based on the file extension
             filtered_duplicates_result.sort_by(|a, b| {
-                a.1.get_path()
+                a.first_path()
                     .extension()
                     .unwrap_or(&OsStr::default())
-                    .cmp(&b.1.get_path().extension().unwrap_or(&OsStr::default()))
+                    .cmp(b.first_path().extension().unwrap_or(&OsStr::default()))
+            });
+        }
+        SortBy::DetectedType => {
+            // Sorts the duplicates based on the type detected from the content, leaving the unknown types at the end
+            filtered_duplicates_result.sort_by_cached_key(|a| {
+                let file_type = detect_type(a.first_path());
+                (file_type.is_none(), file_type.map(|x| x.mime_type))
             });
         }
         SortBy::FileSize => {
             // Sorts the duplicates based on the file sizes
-            filtered_duplicates_result.sort_by(|a, b| {
-                let x = arc_capacities.get(a.0).unwrap();
-                let x2 = arc_capacities.get(b.0).unwrap();
-                x.cast().total_cmp(&x2.cast())
-            });
+            filtered_duplicates_result.sort_by_key(|a| a.bytes_each);
         }
         SortBy::Both => {
             // Sorts the duplicates based on the file sizes
-            filtered_duplicates_result.sort_by(|a, b| {
-                let x = arc_capacities.get(a.0).unwrap();
-                let x2 = arc_capacities.get(b.0).unwrap();
-                x.cast().total_cmp(&x2.cast())
-            });
+            filtered_duplicates_result.sort_by_key(|a| a.bytes_each);
 
             // Sorts the duplicates based on the file extension
             filtered_duplicates_result.sort_by(|a, b| {
-                a.1.get_path()
+                a.first_path()
                     .extension()
                     .unwrap_or(&OsStr::default())
-                    .cmp(&b.1.get_path().extension().unwrap_or(&OsStr::default()))
+                    .cmp(b.first_path().extension().unwrap_or(&OsStr::default()))
             });
         }
     };
 
     match sort_by {
-        SortBy::FileType => {}
+        SortBy::FileType | SortBy::DetectedType => {}
         SortBy::FileSize | SortBy::Both => {
             if let Some(o) = order_by {
                 match o {
@@ -395,97 +846,239 @@ where
 
     log(LogLevel::INFO, "Finished\n");
 
-    if print_config.file.is_none() {
-        println!("######## {} ########", "Report".bright_yellow().blink());
-        // Prints the duplicates to the Screen
-        for (u, (i, k)) in filtered_duplicates_result.into_iter().enumerate() {
-            let x = arc_capacities.get(i).unwrap();
-            let y = human_bytes(x.cast());
-            duplicates_total_size += x.cast() as u64;
-            let list = k.clone().into_iter().collect::<Vec<_>>();
-
-            println!(
-                "\nClone {:?}, {} ({} bytes) each * {}",
-                u+1,
-                y,
-                x.cast(),
-                list.len()
-            );
-            for i in list {
-                println!("      {}", i.to_string().bright_blue());
-            }
-        }
-    } else {
+    // With several source directories, every file is prefixed with the number of the directory it came from
+    let source_dirs = print_config.source_dirs;
+    let root_of = |path: &Path| source_dirs.iter().position(|x| path.starts_with(x)).filter(|_| source_dirs.len() > 1);
+    let label = |path: &Path| root_of(path).map(|i| format!("[{}] ", i + 1)).unwrap_or_default();
+
+    if let Some(file) = print_config.file {
         // Write the output to a file
-        let mut writer = BufWriter::new(print_config.file.unwrap());
+        let mut writer = BufWriter::new(file);
 
         log(LogLevel::INFO, "Writing the output to the file");
 
         match print_config.output_style {
             OutputStyle::Default => {
-                for (u, (i, k)) in filtered_duplicates_result.into_iter().enumerate() {
-                    let x = arc_capacities.get(i).unwrap();
-                    let y = human_bytes(x.cast());
-                    let list = k.clone().into_iter().collect::<Vec<_>>();
-
-                    duplicates_total_size += x.cast() as u64;
-
-                    let header = format!(
-                        "\nClone {:?}, {} ({} bytes) each * {}\n",
-                        u+1,
-                        y,
-                        x.cast(),
-                        list.len()
-                    );
+                for (u, group) in filtered_duplicates_result.iter().enumerate() {
+                    let header = format!("\n{}\n", group_header(u+1, group, print_config.unique));
                     let _ = writer.write(header.as_bytes());
 
-                    for i in k.clone().into_iter() {
-                        let message = format!("      {:?}\n", i);
+                    for i in group.paths.iter() {
+                        let message = format!("      {}{:?}{}{}\n", label(i), i, distance(group, i), type_note(&mismatches, i));
+                        let _ = writer.write(message.as_bytes());
+                        for link in group.hardlinks.get(i).into_iter().flatten() {
+                            let message = format!("          (hardlink) {:?}\n", link);
+                            let _ = writer.write(message.as_bytes());
+                        }
+                    }
+                    for i in group.references.iter() {
+                        let message = format!("      (reference) {:?}{}{}\n", i, distance(group, i), type_note(&mismatches, i));
+                        let _ = writer.write(message.as_bytes());
+                        for link in group.hardlinks.get(i).into_iter().flatten() {
+                            let message = format!("          (hardlink) {:?}\n", link);
+                            let _ = writer.write(message.as_bytes());
+                        }
+                    }
+                    for pair in group.pairs.iter() {
+                        let message = format!("          ({}) {:?} ~ {:?}\n", pair_score(pair), pair.a, pair.b);
                         let _ = writer.write(message.as_bytes());
                     }
                 }
             }
             OutputStyle::JSON => {
                 let mut print_json_array = Vec::new();
-                let mut json_output = String::new();
 
-                for (u, (i, k)) in filtered_duplicates_result.into_iter().enumerate() {
-                    let mut print_json_object = PrinterJSONObject {
-                        duplicate_group_no: 0,
-                        duplicate_group_count: 0,
-                        duplicate_group_bytes_each: 0,
-                        duplicate_list: Vec::new(),
-                    };
+                for (u, group) in filtered_duplicates_result.iter().enumerate() {
+                    print_json_array.push(PrinterJSONObject {
+                        duplicate_group_no: u+1,
+                        duplicate_group_count: group.paths.len() + group.references.len(),
+                        duplicate_group_bytes_each: group.bytes_each as usize,
+                        duplicate_list: group.paths.iter().map(|i| i.to_string()).collect(),
+                        confirmed_by: (!print_config.unique).then_some(group.confirmed_by),
+                        checksum: group.digest.as_ref().map(|x| x.to_string()),
+                        hardlinks: group
+                            .hardlinks
+                            .iter()
+                            .map(|(k, v)| (k.to_string(), v.iter().map(|x| x.to_string()).collect()))
+                            .collect(),
+                        reference_list: group.references.iter().map(|i| i.to_string()).collect(),
+                        roots: group
+                            .paths
+                            .iter()
+                            .filter_map(|i| root_of(i).map(|x| (i.to_string(), source_dirs[x].to_string())))
+                            .collect(),
+                        distances: group.distances.iter().map(|(k, v)| (k.to_string(), *v)).collect(),
+                        size_not_matched: !group.size_matched(),
+                        sizes: group.sizes.iter().map(|(k, v)| (k.to_string(), *v)).collect(),
+                        type_mismatches: group
+                            .paths
+                            .iter()
+                            .chain(group.references.iter())
+                            .filter_map(|i| mismatches.get(i).map(|x| (i.to_string(), x.mime_type.to_owned())))
+                            .collect(),
+                        modified: group
+                            .paths
+                            .iter()
+                            .chain(group.references.iter())
+                            .filter_map(|i| {
+                                let modified = fs::metadata(i).and_then(|x| x.modified()).ok()?;
+                                Some((i.to_string(), humantime::format_rfc3339_nanos(modified).to_string()))
+                            })
+                            .collect(),
+                        similar_pairs: group
+                            .pairs
+                            .iter()
+                            .map(|x| PrinterJSONPair {
+                                a: x.a.to_string(),
+                                b: x.b.to_string(),
+                                score: x.score,
+                                shared_bytes: x.shared_bytes,
+                            })
+                            .collect(),
+                    });
+                }
 
-                    let x = arc_capacities.get(i).unwrap();
+                let report = PrinterJSONReport {
+                    version: REPORT_VERSION,
+                    kind: if print_config.unique { ReportKind::Unique } else { ReportKind::Clones },
+                    hash_algorithm: print_config.hash_algorithm,
+                    source_dirs: source_dirs.iter().map(|x| x.to_string()).collect(),
+                    match_criteria: print_config.match_criteria.iter().map(|x| x.to_string()).collect(),
+                    stages,
+                    reclaimable_bytes: reclaimable_size,
+                    dedupable_bytes: print_config.dedupable_bytes,
+                    owners: owners.clone(),
+                    duplicate_groups: print_json_array,
+                };
 
-                    duplicates_total_size += x.cast() as u64;
+                // Serialize it to a JSON string.
+                if let Ok(json_output) = serde_json::to_string_pretty(&report) {
+                    let _ = writer.write(json_output.as_bytes());
+                } else {
+                    log(LogLevel::ERROR, "Failed to Serialize to JSON String")
+                }
+            }
+        };
 
-                    print_json_object.duplicate_group_no = u+1;
-                    print_json_object.duplicate_group_count =
-                        k.clone().into_iter().collect::<Vec<_>>().len();
-                    print_json_object.duplicate_group_bytes_each = x.cast() as usize;
+        log(LogLevel::INFO, "Finished writing to the file");
+    } else {
+        println!("######## {} ########", "Report".bright_yellow().blink());
+        // Prints the duplicates to the Screen
+        for (u, group) in filtered_duplicates_result.iter().enumerate() {
+            println!("\n{}", group_header(u+1, group, print_config.unique));
+            for i in group.paths.iter() {
+                println!(
+                    "      {}{}{}{}",
+                    label(i),
+                    i.to_string().bright_blue(),
+                    distance(group, i),
+                    type_note(&mismatches, i).bright_red()
+                );
+                for link in group.hardlinks.get(i).into_iter().flatten() {
+                    println!("          (hardlink) {}", link.to_string().blue());
+                }
+            }
+            for i in group.references.iter() {
+                println!(
+                    "      (reference) {}{}{}",
+                    i.to_string().bright_green(),
+                    distance(group, i),
+                    type_note(&mismatches, i).bright_red()
+                );
+                for link in group.hardlinks.get(i).into_iter().flatten() {
+                    println!("          (hardlink) {}", link.to_string().green());
+                }
+            }
+            for pair in group.pairs.iter() {
+                println!(
+                    "          ({}) {} ~ {}",
+                    pair_score(pair).bright_yellow(),
+                    pair.a.to_string().blue(),
+                    pair.b.to_string().blue()
+                );
+            }
+        }
+    }
 
-                    for i in k.clone().into_iter() {
-                        print_json_object.duplicate_list.push(i.to_string());
-                    }
+    DuplicatesSummary {
+        clones_count: duplicates_count,
+        clones_total_size: duplicates_total_size,
+        reclaimable_size,
+        dedupable_size: print_config.dedupable_bytes,
+        owners,
+    }
+}
 
-                    print_json_array.push(print_json_object);
+// The score printed before a pair. Pairs of chunked files show the share of the smaller file and the bytes in common
+fn pair_score(pair: &SimilarPair) -> String {
+    match pair.shared_bytes {
+        Some(shared_bytes) => format!("{:.1}%, {} shared", pair.score * 100.0, human_bytes(shared_bytes as f64)),
+        None => format!("{:.3}", pair.score),
+    }
+}
 
-                    // Serialize it to a JSON string.
-                    if let Ok(o) = serde_json::to_string_pretty(&print_json_array) {
-                        json_output = o;
-                    } else {
-                        log(LogLevel::ERROR, "Failed to Serialize to JSON String")
-                    }
-                }
+// The perceptual hash distance printed after an image
+fn distance(group: &CloneGroup, path: &Path) -> String {
+    group.distances.get(path).map(|x| format!(" (distance {})", x)).unwrap_or_default()
+}
 
-                let _ = writer.write(json_output.as_bytes());
-            }
-        };
+// The detected type printed after a file whose extension does not fit its content
+fn type_note(mismatches: &HashMap<PathBuf, FileType>, path: &Path) -> String {
+    mismatches.get(path).map(|x| format!(" (content is {})", x.mime_type)).unwrap_or_default()
+}
 
-        log(LogLevel::INFO, "Finished writing to the file");
+// The first line printed for a group
+fn group_header(group_no: usize, group: &CloneGroup, unique: bool) -> String {
+    if unique {
+        format!(
+            "Unique {:?}, {} ({} bytes)",
+            group_no,
+            human_bytes(group.bytes_each as f64),
+            group.bytes_each
+        )
+    } else if !group.size_matched() {
+        format!(
+            "Clone {:?}, {} files of up to {} ({} bytes), not matched on their size (confirmed by {})",
+            group_no,
+            group.paths.len() + group.references.len(),
+            human_bytes(group.bytes_each as f64),
+            group.bytes_each,
+            group.confirmed_by
+        )
+    } else if !group.confirmed_by.finds_copies() {
+        format!(
+            "Similar {:?}, {} files of up to {} ({} bytes) each (confirmed by {})",
+            group_no,
+            group.paths.len(),
+            human_bytes(group.bytes_each as f64),
+            group.bytes_each,
+            group.confirmed_by
+        )
+    } else {
+        format!(
+            "Clone {:?}, {} ({} bytes) each * {} (confirmed by {})",
+            group_no,
+            human_bytes(group.bytes_each as f64),
+            group.bytes_each,
+            group.paths.len() + group.references.len(),
+            group.confirmed_by
+        )
     }
+}
 
-    (duplicates_count, duplicates_total_size)
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Stage::Metadata => write!(f, "metadata"),
+            Stage::FileSize => write!(f, "file size"),
+            Stage::PartialHash => write!(f, "partial checksum"),
+            Stage::FullHash => write!(f, "full checksum"),
+            Stage::ByteCompare => write!(f, "byte-by-byte comparison"),
+            Stage::DirectoryTree => write!(f, "directory tree"),
+            Stage::Similarity => write!(f, "similarity"),
+            Stage::FileName => write!(f, "similar file name"),
+            Stage::PerceptualHash => write!(f, "perceptual image hash"),
+            Stage::ChunkOverlap => write!(f, "content-defined chunking"),
+        }
+    }
 }
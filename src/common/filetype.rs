@@ -0,0 +1,134 @@
+// Copyright (c) 2024 Venkatesh Omkaram
+
+// Detects the type of a file from the magic bytes at the start of its content, as the extensions of camera dumps and
+// recovered files are often wrong or missing. The types found are cached for the whole hunt. The checksum hunts record
+// the first bytes the partial checksum read, so only the files which are not hashed are opened to read them
+use std::{
+    fs::File,
+    io::{self, Read},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use hashbrown::HashMap;
+use infer::MatcherType;
+use lazy_static::lazy_static;
+
+use crate::common::hasher::PARTIAL_CHECKSUM_CHUNK;
+
+/// Number of bytes taken from the front of a file to detect its type, as many as the partial checksum takes
+pub const SNIFF_LENGTH: usize = PARTIAL_CHECKSUM_CHUNK;
+
+/// The broad kinds of content which can be passed to `--type`, besides an extension or a MIME type
+pub const KINDS: [&str; 9] = ["app", "archive", "audio", "book", "document", "font", "image", "text", "video"];
+
+/// Other extensions which are commonly used for a detected type, keyed by the extension of that type. The first bytes
+/// of an Office document or a Java archive only tell that it is a ZIP or an OLE file
+const EXTENSION_ALIASES: [(&str, &[&str]); 11] = [
+    ("jpg", &["jpeg", "jpe", "jfif"]),
+    ("tif", &["tiff"]),
+    ("mov", &["qt"]),
+    ("ogg", &["oga", "ogv", "opus"]),
+    ("exe", &["dll", "sys", "efi", "scr"]),
+    ("gz", &["tgz", "gzip"]),
+    ("bz2", &["tbz", "tbz2"]),
+    ("xz", &["txz"]),
+    ("zip", &["jar", "war", "apk", "ipa", "whl", "xpi", "cbz", "kmz", "nupkg", "docx", "xlsx", "pptx", "odt", "ods", "odp", "epub"]),
+    ("msi", &["doc", "xls", "ppt", "msg", "vsd"]),
+    ("html", &["htm"]),
+];
+
+lazy_static! {
+    /// The types detected so far, keyed by the file path. A file whose type is not known is recorded as None
+    static ref DETECTED_TYPES: Mutex<HashMap<PathBuf, Option<FileType>>> = Mutex::new(HashMap::new());
+}
+
+/// The type of a file detected from its content
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FileType {
+    pub mime_type: &'static str,
+    /// The usual extension of the type, without its dot
+    pub extension: &'static str,
+    /// One of the `KINDS`
+    pub kind: &'static str,
+}
+
+impl FileType {
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        infer::get(&bytes[..bytes.len().min(SNIFF_LENGTH)]).map(|x| FileType {
+            mime_type: x.mime_type(),
+            extension: x.extension(),
+            kind: match x.matcher_type() {
+                MatcherType::App => "app",
+                MatcherType::Archive => "archive",
+                MatcherType::Audio => "audio",
+                MatcherType::Book => "book",
+                MatcherType::Doc => "document",
+                MatcherType::Font => "font",
+                MatcherType::Image => "image",
+                MatcherType::Text | MatcherType::Custom => "text",
+                MatcherType::Video => "video",
+            },
+        })
+    }
+
+    /// Whether the type is the given kind, extension or MIME type, for example `image`, `pdf` or `video/mp4`
+    pub fn is(&self, name: &str) -> bool {
+        let name = name.to_lowercase();
+        self.kind == name || self.mime_type == name || self.accepts_extension(&name)
+    }
+
+    /// Whether the extension, compared ignoring its case, is usual for the type
+    pub fn accepts_extension(&self, extension: &str) -> bool {
+        let extension = extension.to_lowercase();
+        self.extension.eq_ignore_ascii_case(&extension)
+            || EXTENSION_ALIASES
+                .iter()
+                .any(|(x, aliases)| *x == self.extension && aliases.contains(&extension.as_str()))
+    }
+}
+
+/// Whether the name can be passed to `--type`, i.e. a kind, or an extension or a MIME type which can be detected
+pub fn is_known_type(name: &str) -> bool {
+    let name = name.to_lowercase();
+    KINDS.contains(&name.as_str())
+        || infer::is_supported(&name)
+        || infer::is_mime_supported(&name)
+        || EXTENSION_ALIASES.iter().any(|(_, aliases)| aliases.contains(&name.as_str()))
+}
+
+/// Detects the type of a file from its first bytes. Files which cannot be read and files of an unknown type give None
+pub fn detect_type(path: &Path) -> Option<FileType> {
+    if let Some(file_type) = DETECTED_TYPES.lock().unwrap().get(path) {
+        return *file_type;
+    }
+
+    let file_type = read_head(path).ok().and_then(|x| FileType::from_bytes(&x));
+    DETECTED_TYPES.lock().unwrap().insert(path.to_path_buf(), file_type);
+    file_type
+}
+
+/// Records the type of a file from its first bytes, which were read for another purpose such as the partial checksum,
+/// so the file is not opened again to detect its type
+pub fn record_head(path: &Path, head: &[u8]) {
+    DETECTED_TYPES.lock().unwrap().entry_ref(path).or_insert_with(|| FileType::from_bytes(head));
+}
+
+/// Whether the detected type of a file is one of the kinds, extensions or MIME types. Every file is when there are none
+pub fn is_one_of(path: &Path, types: &[String]) -> bool {
+    types.is_empty() || detect_type(path).is_some_and(|x| types.iter().any(|name| x.is(name)))
+}
+
+/// The type detected from the content of a file when its extension is missing or does not fit that type. Text is left
+/// out, as scripts and markup are told apart by a few loose markers only
+pub fn type_mismatch(path: &Path) -> Option<FileType> {
+    detect_type(path).filter(|x| {
+        x.kind != "text" && !path.extension().is_some_and(|e| x.accepts_extension(&e.to_string_lossy()))
+    })
+}
+
+fn read_head(path: &Path) -> io::Result<Vec<u8>> {
+    let mut head = Vec::with_capacity(SNIFF_LENGTH);
+    File::open(path)?.take(SNIFF_LENGTH as u64).read_to_end(&mut head)?;
+    Ok(head)
+}
@@ -0,0 +1,172 @@
+// Copyright (c) 2024 Venkatesh Omkaram
+
+// The filters which decide what the walk picks up. Excluded directories are pruned while walking, so nothing under them
+// is read, and the files are matched against the extensions, the include and the exclude patterns. The content of a
+// file is only read to detect its type, once every other filter accepted it
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use hashbrown::HashMap;
+use ignore::{
+    gitignore::{Gitignore, GitignoreBuilder},
+    Match,
+};
+use regex::RegexSet;
+
+use crate::common::core::{log, LogLevel};
+
+/// Names of the files holding gitignore rules. A rule of a later file wins over a rule of an earlier one
+const IGNORE_FILES: [&str; 3] = [".gitignore", ".ignore", ".clonehunterignore"];
+
+/// Glob patterns split by what they are matched against. A pattern without a `/` is matched against the name of the
+/// file or directory, like `node_modules` or `*.tmp`, and any other pattern against the full path, like `build/**`.
+/// A relative pattern may match from any directory down
+struct Patterns {
+    names: GlobSet,
+    paths: GlobSet,
+    count: usize,
+}
+
+impl Patterns {
+    fn new(patterns: &[String]) -> Result<Self, String> {
+        let mut names = GlobSetBuilder::new();
+        let mut paths = GlobSetBuilder::new();
+
+        for pattern in patterns {
+            let invalid = |e: globset::Error| format!("Invalid glob pattern {:?}: {}", pattern, e);
+            if !pattern.contains('/') {
+                names.add(Glob::new(pattern).map_err(invalid)?);
+            } else if pattern.starts_with('/') || pattern.starts_with("**") {
+                paths.add(Glob::new(pattern).map_err(invalid)?);
+            } else {
+                // A relative pattern like `src/**` may start at any directory
+                paths.add(Glob::new(&format!("**/{}", pattern)).map_err(invalid)?);
+            }
+        }
+
+        Ok(Patterns {
+            names: names.build().map_err(|e| e.to_string())?,
+            paths: paths.build().map_err(|e| e.to_string())?,
+            count: patterns.len(),
+        })
+    }
+
+    fn is_match(&self, path: &Path) -> bool {
+        path.file_name().is_some_and(|x| self.names.is_match(x)) || self.paths.is_match(path)
+    }
+}
+
+/// The rules of the ignore files found in the directories walked. The ignore files of a directory are read the first
+/// time a path under it is matched. The ignore files above the roots of the walk are not read, so the rules of the home
+/// directory or of an enclosing repository do not hide files from an unrelated hunt
+struct IgnoreRules {
+    roots: Vec<PathBuf>,
+    by_dir: Mutex<HashMap<PathBuf, Option<Arc<Gitignore>>>>,
+}
+
+impl IgnoreRules {
+    // The deepest directory with a rule matching the path decides, so a rule can be overridden further down like in git
+    fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        for dir in path.ancestors().skip(1) {
+            if !self.roots.iter().any(|x| dir.starts_with(x)) {
+                break;
+            }
+            if let Some(rules) = self.rules(dir) {
+                match rules.matched(path, is_dir) {
+                    Match::Ignore(_) => return true,
+                    Match::Whitelist(_) => return false,
+                    Match::None => {}
+                }
+            }
+        }
+        false
+    }
+
+    fn rules(&self, dir: &Path) -> Option<Arc<Gitignore>> {
+        if let Some(rules) = self.by_dir.lock().unwrap().get(dir) {
+            return rules.clone();
+        }
+
+        let mut builder = GitignoreBuilder::new(dir);
+        let mut found = false;
+        for name in IGNORE_FILES {
+            let file = dir.join(name);
+            if file.is_file() {
+                found = true;
+                if let Some(e) = builder.add(&file) {
+                    log(LogLevel::ERROR, format!("Failed to read some of the rules in {:?} due to {}", file, e).as_str());
+                }
+            }
+        }
+
+        let rules = if found { builder.build().ok().map(Arc::new) } else { None };
+        self.by_dir.lock().unwrap().insert(dir.to_path_buf(), rules.clone());
+        rules
+    }
+}
+
+/// The include and exclude filters of the walk
+pub struct PathFilter {
+    /// Lowercased extensions without their dot. Empty when every extension is accepted
+    extensions: Vec<String>,
+    include: Patterns,
+    exclude: Patterns,
+    exclude_regex: RegexSet,
+    /// Absent when the ignore files are not honoured
+    ignore_rules: Option<IgnoreRules>,
+}
+
+impl PathFilter {
+    /// Builds the filters from the comma separated extensions, the glob patterns and the regular
+    /// expressions. The `.gitignore`, `.ignore` and `.clonehunterignore` files in the roots of the walk and under them
+    /// are honoured when the roots are given
+    pub fn new(
+        extensions: Option<&str>,
+        include: &[String],
+        exclude: &[String],
+        exclude_regex: &[String],
+        ignore_roots: Option<&[PathBuf]>,
+    ) -> Result<Self, String> {
+        Ok(PathFilter {
+            extensions: extensions
+                .into_iter()
+                .flat_map(|x| x.split(','))
+                .map(|x| x.trim().trim_start_matches('.').to_lowercase())
+                .filter(|x| !x.is_empty())
+                .collect(),
+            include: Patterns::new(include)?,
+            exclude: Patterns::new(exclude)?,
+            exclude_regex: RegexSet::new(exclude_regex).map_err(|e| format!("Invalid regular expression: {}", e))?,
+            ignore_rules: ignore_roots.map(|roots| IgnoreRules {
+                roots: roots.to_vec(),
+                by_dir: Mutex::new(HashMap::new()),
+            }),
+        })
+    }
+
+    /// Whether the directory and everything under it is left out of the walk
+    pub fn excludes_dir(&self, path: &Path) -> bool {
+        self.is_excluded(path) || self.ignore_rules.as_ref().is_some_and(|x| x.is_ignored(path, true))
+    }
+
+    /// Whether the file has one of the extensions, matches an include pattern when there are any, and is not excluded.
+    /// The extensions are compared ignoring their case
+    pub fn accepts_file(&self, path: &Path) -> bool {
+        let extension_matches = self.extensions.is_empty()
+            || path
+                .extension()
+                .is_some_and(|x| self.extensions.contains(&x.to_string_lossy().to_lowercase()));
+
+        extension_matches
+            && (self.include.count == 0 || self.include.is_match(path))
+            && !self.is_excluded(path)
+            && !self.ignore_rules.as_ref().is_some_and(|x| x.is_ignored(path, false))
+    }
+
+    fn is_excluded(&self, path: &Path) -> bool {
+        self.exclude.is_match(path) || self.exclude_regex.is_match(&path.to_string_lossy())
+    }
+}
@@ -0,0 +1,207 @@
+// Copyright (c) 2024 Venkatesh Omkaram
+
+// Content hashers which can be plugged into the checksum stages of the hunt
+use std::{
+    fmt,
+    fs::File,
+    io::{self, BufReader, Read},
+    path::Path,
+};
+
+use crate::common::config::HashAlgorithm;
+
+/// Files up to this length are hashed in full by the partial checksum
+pub const PARTIAL_CHECKSUM_LIMIT: u64 = 2048;
+
+/// Number of bytes taken from the front and the back of a file for the partial checksum
+pub const PARTIAL_CHECKSUM_CHUNK: usize = 1024;
+
+/// Buffer size used while reading the entire content of a file
+pub const READ_BUFFER_SIZE: usize = 64 * 1024;
+
+/// A streaming hasher over the content of a file
+pub trait ContentHasher {
+    fn update(&mut self, data: &[u8]);
+    fn finish(self: Box<Self>) -> Digest;
+}
+
+/// The raw bytes of a content checksum
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct Digest(pub Vec<u8>);
+
+struct Md5Hasher(md5::Context);
+
+impl ContentHasher for Md5Hasher {
+    fn update(&mut self, data: &[u8]) {
+        self.0.consume(data);
+    }
+
+    fn finish(self: Box<Self>) -> Digest {
+        Digest(self.0.compute().0.to_vec())
+    }
+}
+
+struct Sha256Hasher(sha2::Sha256);
+
+impl ContentHasher for Sha256Hasher {
+    fn update(&mut self, data: &[u8]) {
+        sha2::Digest::update(&mut self.0, data);
+    }
+
+    fn finish(self: Box<Self>) -> Digest {
+        Digest(sha2::Digest::finalize(self.0).to_vec())
+    }
+}
+
+struct Blake3Hasher(blake3::Hasher);
+
+impl ContentHasher for Blake3Hasher {
+    fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    fn finish(self: Box<Self>) -> Digest {
+        Digest(self.0.finalize().as_bytes().to_vec())
+    }
+}
+
+struct Xxh3Hasher(xxhash_rust::xxh3::Xxh3);
+
+impl ContentHasher for Xxh3Hasher {
+    fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    fn finish(self: Box<Self>) -> Digest {
+        Digest(self.0.digest128().to_be_bytes().to_vec())
+    }
+}
+
+impl HashAlgorithm {
+    /// Creates a fresh hasher for this algorithm
+    pub fn hasher(&self) -> Box<dyn ContentHasher> {
+        match self {
+            HashAlgorithm::Md5 => Box::new(Md5Hasher(md5::Context::new())),
+            HashAlgorithm::Sha256 => Box::new(Sha256Hasher(<sha2::Sha256 as sha2::Digest>::new())),
+            HashAlgorithm::Blake3 => Box::new(Blake3Hasher(blake3::Hasher::new())),
+            HashAlgorithm::Xxh3 => Box::new(Xxh3Hasher(xxhash_rust::xxh3::Xxh3::new())),
+        }
+    }
+}
+
+/// Generates a checksum based on the first and last 1024 bytes and the file length.
+/// Files which are not longer than PARTIAL_CHECKSUM_LIMIT are hashed in full
+pub fn partial_checksum(path: &Path, algorithm: HashAlgorithm) -> io::Result<Digest> {
+    partial_checksum_with_head(path, algorithm).map(|x| x.0)
+}
+
+/// Generates the partial checksum, and returns it along with the first bytes of the file, up to PARTIAL_CHECKSUM_CHUNK
+/// of them, for the callers which have another use for these bytes
+pub fn partial_checksum_with_head(path: &Path, algorithm: HashAlgorithm) -> io::Result<(Digest, Vec<u8>)> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut hasher = algorithm.hasher();
+
+    let file_length = path.metadata()?.len();
+
+    let head = if file_length > PARTIAL_CHECKSUM_LIMIT {
+        let mut head = vec![0; PARTIAL_CHECKSUM_CHUNK];
+        reader.read_exact(&mut head)?;
+        hasher.update(&head);
+
+        reader.seek_relative(file_length as i64 - 2 * PARTIAL_CHECKSUM_CHUNK as i64)?;
+
+        let mut buffer = vec![0; PARTIAL_CHECKSUM_CHUNK];
+        reader.read_exact(&mut buffer)?;
+        hasher.update(&buffer);
+
+        hasher.update(&file_length.to_le_bytes());
+        head
+    } else {
+        let mut buffer_full = Vec::with_capacity(file_length as usize);
+
+        reader.read_to_end(&mut buffer_full)?;
+        hasher.update(&buffer_full);
+        buffer_full.truncate(PARTIAL_CHECKSUM_CHUNK);
+        buffer_full
+    };
+
+    Ok((hasher.finish(), head))
+}
+
+/// Generates a checksum on the entire content of the file
+pub fn full_checksum(path: &Path, algorithm: HashAlgorithm) -> io::Result<Digest> {
+    let mut reader = File::open(path)?;
+    let mut hasher = algorithm.hasher();
+    let mut buffer = vec![0; READ_BUFFER_SIZE];
+
+    loop {
+        let read = reader.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+
+    Ok(hasher.finish())
+}
+
+/// Generates both the partial and the full checksum in a single pass over a stream of the given length.
+/// Used for the content which cannot be seeked, such as the members of compressed archives
+pub fn stream_checksums<R: Read>(mut reader: R, length: u64, algorithm: HashAlgorithm) -> io::Result<(Digest, Digest)> {
+    let mut hasher = algorithm.hasher();
+    let mut buffer = vec![0; READ_BUFFER_SIZE];
+    let mut front = Vec::with_capacity(PARTIAL_CHECKSUM_CHUNK);
+    let mut back: Vec<u8> = Vec::with_capacity(2 * PARTIAL_CHECKSUM_CHUNK);
+
+    loop {
+        let read = reader.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+
+        let wanted = (PARTIAL_CHECKSUM_CHUNK - front.len()).min(read);
+        front.extend_from_slice(&buffer[..wanted]);
+
+        back.extend_from_slice(&buffer[read.saturating_sub(PARTIAL_CHECKSUM_CHUNK)..read]);
+        if back.len() > PARTIAL_CHECKSUM_CHUNK {
+            back.drain(..back.len() - PARTIAL_CHECKSUM_CHUNK);
+        }
+    }
+
+    let full = hasher.finish();
+
+    if length <= PARTIAL_CHECKSUM_LIMIT {
+        return Ok((full.clone(), full));
+    }
+
+    let mut hasher = algorithm.hasher();
+    hasher.update(&front);
+    hasher.update(&back);
+    hasher.update(&length.to_le_bytes());
+
+    Ok((hasher.finish(), full))
+}
+
+impl Digest {
+    /// Parses a hex encoded checksum, as written to the reports and the hash cache
+    pub fn from_hex(hex: &str) -> Option<Digest> {
+        if !hex.len().is_multiple_of(2) {
+            return None;
+        }
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+            .collect::<Option<Vec<u8>>>()
+            .map(Digest)
+    }
+}
+
+impl fmt::Display for Digest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for byte in &self.0 {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
@@ -1,3 +1,8 @@
 pub mod core;
 pub mod config;
-pub mod trait_defs;
\ No newline at end of file
+pub mod trait_defs;
+pub mod hasher;
+pub mod cache;
+pub mod archive;
+pub mod filter;
+pub mod filetype;
@@ -12,7 +12,7 @@ pub(crate) trait DirectoryMetaData {
     fn get_path(&self) -> PathBuf;
 }
 
-impl<'a> DirectoryMetaData for &'a Result<jwalk::DirEntry<((), ())>, jwalk::Error> {
+impl DirectoryMetaData for &Result<jwalk::DirEntry<((), ())>, jwalk::Error> {
     fn get_metadata(&self) -> fs::Metadata {
         self.as_ref().unwrap().metadata().unwrap()
     }
@@ -22,7 +22,7 @@ impl<'a> DirectoryMetaData for &'a Result<jwalk::DirEntry<((), ())>, jwalk::Erro
     }
 }
 
-impl<'a> DirectoryMetaData for &'a Result<DirEntry, std::io::Error> {
+impl DirectoryMetaData for &Result<DirEntry, std::io::Error> {
     fn get_metadata(&self) -> fs::Metadata {
         self.as_ref().unwrap().metadata().unwrap()
     }
@@ -44,6 +44,7 @@ impl AsF64 for u64 {
 }
 
 // A simple trait to return length of its implementors. Pretty useful in Function which takes Generic arguments
+#[allow(clippy::len_without_is_empty)]
 pub trait ExactSize {
     fn len(&self) -> usize;
 }
@@ -66,13 +67,13 @@ pub trait Paths {
 
 impl Paths for Vec<OsString> {
     fn get_path(&self) -> PathBuf {
-        self.get(0).unwrap().into()
+        self.first().unwrap().into()
     }
 }
 
 impl Paths for Vec<PathBuf> {
     fn get_path(&self) -> PathBuf {
-        self.get(0).unwrap().to_path_buf()
+        self.first().unwrap().to_path_buf()
     }
 }
 
@@ -1,17 +1,85 @@
-use std::fs::remove_file;
+use std::{
+    collections::BTreeMap,
+    fs::{self, remove_file},
+    io::{stdin, IsTerminal},
+    path::Path,
+    time::UNIX_EPOCH,
+};
 
-use clonehunter::common::core::{confirmation, log, LogLevel, PrinterJSONObject};
+use clonehunter::common::{
+    archive::is_member,
+    config::{DeleteOptions, HashAlgorithm, KeepPolicy},
+    core::{confirmation, file_identity, log, LogLevel, PrinterJSONObject, PrinterJSONReport, Stage},
+    hasher::{full_checksum, Digest},
+};
 use colored::Colorize;
+use hashbrown::HashMap;
 use human_bytes::human_bytes;
 
-pub fn delete(input_json: Vec<PrinterJSONObject>, dry_run: bool) {
+use crate::quarantine::Quarantine;
+
+/// What becomes of the files which go
+enum Disposal {
+    Delete,
+    Trash,
+    Quarantine(Quarantine),
+}
+
+impl Disposal {
+    /// What is printed for each file which goes
+    fn done(&self) -> &'static str {
+        match self {
+            Disposal::Delete => "Deleted",
+            Disposal::Trash => "Trashed",
+            Disposal::Quarantine(_) => "Quarantined",
+        }
+    }
+}
+
+/// A file of the report which changed since the report was written, so it was left alone
+pub struct StaleFile {
+    pub path: String,
+    pub reason: String,
+}
+
+pub fn delete(report: PrinterJSONReport, options: &DeleteOptions) {
+    let input_json = report.duplicate_groups;
+    let dry_run = options.dry_run;
+    // Reports written without a checksum are verified with the default algorithm
+    let algorithm = report.hash_algorithm.unwrap_or(HashAlgorithm::Md5);
+    let mut disposal = match &options.quarantine {
+        Some(dir) => match Quarantine::new(Path::new(dir), algorithm) {
+            Ok(quarantine) => Disposal::Quarantine(quarantine),
+            Err(e) => {
+                log(LogLevel::ERROR, format!("Failed to use the quarantine directory {} due to {}", dir, e).as_str());
+                return;
+            }
+        },
+        None => choose_disposal(options, stdin().is_terminal()),
+    };
     let mut failed_to_delete = Vec::new();
+    let mut stale_files = Vec::new();
+    let mut removed_bytes = 0;
     let mut total_files_size = 0;
     let tota_groups = input_json.len();
-    for i in &input_json {
+    // The files of a group which was not matched on their size are not known to be copies, so they are not counted
+    for i in input_json.iter().filter(|x| !x.size_not_matched) {
         total_files_size += i.duplicate_group_bytes_each;
     }
     log(LogLevel::INFO, format!("Is this a dry run? : {}", dry_run.to_string().blink()).as_str());
+    log(
+        LogLevel::INFO,
+        format!(
+            "The duplicates are : {}",
+            match &disposal {
+                Disposal::Delete => "deleted for good".to_owned(),
+                Disposal::Trash => "moved to the trash".to_owned(),
+                Disposal::Quarantine(quarantine) => format!("moved to the quarantine under {}", quarantine.tree()),
+            }
+        )
+        .as_str(),
+    );
+    log(LogLevel::INFO, format!("Keep policy : {}", describe_policies(&options.keep)).as_str());
     log(
         LogLevel::INFO,
         format!("Found {} group(s) with {} total files size on the disk",
@@ -22,42 +90,21 @@ pub fn delete(input_json: Vec<PrinterJSONObject>, dry_run: bool) {
     if tota_groups != 0 {
         println!("{}", "\nShall I proceed to delete the duplicates?".bright_blue());
         if confirmation() == "Y" {
-            for mut i in input_json {
-                println!(
-                    "Trying deleting {} file(s) in group {} of size {}",
-                    i.duplicate_group_count - 1,
-                    i.duplicate_group_no,
-                    human_bytes(i.duplicate_group_bytes_each as f64)
-                );
-                if let Some(retained_file) = i.duplicate_list.pop() {
-                    let mut last_file_no = 0;
-                    for (l, j) in i.duplicate_list.iter().enumerate() {
-                        last_file_no = l;
-                        if !dry_run {
-                            if let Err(result) = remove_file(j.as_str()) {
-                                failed_to_delete.push(format!("Failed to delete the file {} due to {}", j, result));
-                            } else {
-                                println!("      Deleted the file ({}) :: {}", l, j.bright_blue());
-                            }
-                        } else {
-                            println!("      Deleted the file ({}) :: {}", l, j.bright_blue());
-                        }
-                    }
-                    if i.duplicate_group_bytes_each == 0 {
-                        if let Err(result) = remove_file(retained_file.as_str()) {
-                            failed_to_delete.push(format!("Failed to delete the file {} due to {}", retained_file, result));
-                        } else {
-                            println!("      Deleted the file ({}) :: {}", last_file_no+1, retained_file.bright_blue());
-                        }
-                    } else {
-                        println!(
-                            "\n      Retained the file :: {}\n",
-                            retained_file.bright_green()
-                        );
+            for i in input_json {
+                removed_bytes +=
+                    delete_group(i, options, &report.source_dirs, algorithm, &mut disposal, &mut stale_files, &mut failed_to_delete);
+                // The manifest is kept up to date, so the files moved so far can be restored whatever happens next
+                if let Disposal::Quarantine(quarantine) = &disposal {
+                    if let Err(e) = quarantine.save() {
+                        log(LogLevel::ERROR, format!("Failed to write the manifest {:?} due to {}", quarantine.manifest_path(), e).as_str());
                     }
                 }
             }
 
+            print_stale_files(&stale_files);
+
+            log(LogLevel::INFO, describe_space(&disposal, removed_bytes, dry_run).as_str());
+
             if !failed_to_delete.is_empty() && !dry_run {
                 println!("## {} ##\n", "Error: Looks like there were some failures while deleting certain duplicates. Here is the list".bright_red().bold());
                 for i in failed_to_delete {
@@ -65,6 +112,13 @@ pub fn delete(input_json: Vec<PrinterJSONObject>, dry_run: bool) {
                 }
             } else if dry_run {
                 log(LogLevel::INFO, "Nothing changed. This was a dry run.\n");
+            } else if let Disposal::Trash = disposal {
+                println!("\nLooks like we are done. The duplicates are in the trash, should you miss any of them.\n");
+            } else if let Disposal::Quarantine(quarantine) = &disposal {
+                println!(
+                    "\nLooks like we are done. Should you miss any of the duplicates, run `clonehunter undo {}`\n",
+                    quarantine.manifest_path().display()
+                );
             } else {
                 println!("\nLooks like we are done deleting. Now please don't start crying.\n");
             }
@@ -75,3 +129,626 @@ pub fn delete(input_json: Vec<PrinterJSONObject>, dry_run: bool) {
         println!("\nFound no duplicates. You lucky son of a gun.\n");
     }
 }
+
+// Chooses between deleting the duplicates and moving them to the trash. The trash keeps a way back, so it is used unless
+// asked otherwise when someone is at the terminal
+fn choose_disposal(options: &DeleteOptions, at_terminal: bool) -> Disposal {
+    if options.trash || (!options.no_trash && at_terminal) {
+        Disposal::Trash
+    } else {
+        Disposal::Delete
+    }
+}
+
+// Deletes the duplicates of a group, after checking them against the report and choosing the file which is kept, and
+// returns the bytes of the files which went along with all of their hardlinks
+fn delete_group(
+    mut i: PrinterJSONObject,
+    options: &DeleteOptions,
+    source_dirs: &[String],
+    algorithm: HashAlgorithm,
+    disposal: &mut Disposal,
+    stale_files: &mut Vec<StaleFile>,
+    failed_to_delete: &mut Vec<String>,
+) -> u64 {
+    let dry_run = options.dry_run;
+    let deleted = disposal.done();
+    if i.confirmed_by == Some(Stage::DirectoryTree) {
+        println!(
+            "Skipping group {} as it holds directories. Please delete identical directories yourself",
+            i.duplicate_group_no
+        );
+        return 0;
+    }
+    if i.confirmed_by.is_some_and(|x| !x.finds_copies()) {
+        println!(
+            "Skipping group {} as its files are only similar, not identical",
+            i.duplicate_group_no
+        );
+        return 0;
+    }
+    if i.size_not_matched {
+        println!(
+            "Skipping group {} as its files were not matched on their size, so they are not known to be copies",
+            i.duplicate_group_no
+        );
+        return 0;
+    }
+    // Files inside archives are never touched, and one of the files on the disk is retained
+    let (members, files): (Vec<String>, Vec<String>) =
+        i.duplicate_list.into_iter().partition(|x| is_member(Path::new(x)));
+    i.duplicate_list = files;
+    for member in &members {
+        println!("      Skipping the file inside an archive :: {}", member.bright_yellow());
+    }
+    // The files which changed since the report was written are left alone, and so is a group whose reference copies
+    // all changed
+    let had_references = !i.reference_list.is_empty();
+    let digests = verify_group(&mut i, algorithm, stale_files);
+    if had_references && i.reference_list.is_empty() {
+        println!(
+            "Skipping group {} as none of its reference copies are the same as in the report",
+            i.duplicate_group_no
+        );
+        return 0;
+    }
+    // A group with reference copies keeps all of them, so every file in the duplicate list goes. Any other group keeps
+    // one of its files, even when they are empty
+    let retained_file = if i.reference_list.is_empty() {
+        match choose_keeper(&mut i.duplicate_list, &options.keep, source_dirs) {
+            Some(retained_file) => Some(retained_file),
+            None => return 0,
+        }
+    } else {
+        None
+    };
+    // Every file which goes must still hold the same content as a file which stays
+    let kept_digest = match &retained_file {
+        Some((retained_file, _)) => digests[retained_file].clone(),
+        None => digests[&i.reference_list[0]].clone(),
+    };
+    i.duplicate_list.retain(|x| {
+        let same = digests[x] == kept_digest;
+        if !same {
+            stale_files.push(StaleFile {
+                path: x.clone(),
+                reason: "its content differs from the file which is kept".to_owned(),
+            });
+        }
+        same
+    });
+    println!(
+        "Trying deleting {} file(s) in group {} of size {}",
+        i.duplicate_list.len(),
+        i.duplicate_group_no,
+        human_bytes(i.duplicate_group_bytes_each as f64)
+    );
+    let mut removed_bytes = 0;
+    for (l, j) in i.duplicate_list.iter().enumerate() {
+        // Its hardlinks are matched against it before it is gone
+        let identity = fs::metadata(j).ok().as_ref().and_then(file_identity);
+        let mut all_removed = true;
+        if !dry_run {
+            if let Err(result) = remove(j, &digests[j], disposal) {
+                failed_to_delete.push(format!("Failed to delete the file {} due to {}", j, result));
+                all_removed = false;
+            } else {
+                println!("      {} the file ({}) :: {}", deleted, l, j.bright_blue());
+            }
+        } else {
+            println!("      {} the file ({}) :: {}", deleted, l, j.bright_blue());
+        }
+        // The space of a file is only freed once all of its hardlinks are gone
+        for link in i.hardlinks.get(j).into_iter().flatten() {
+            if identity.is_none() || fs::metadata(link).ok().as_ref().and_then(file_identity) != identity {
+                stale_files.push(StaleFile {
+                    path: link.clone(),
+                    reason: format!("it is no longer a hardlink of {}", j),
+                });
+                continue;
+            }
+            if !dry_run {
+                if let Err(result) = remove(link, &digests[j], disposal) {
+                    failed_to_delete.push(format!("Failed to delete the file {} due to {}", link, result));
+                    all_removed = false;
+                } else {
+                    println!("          {} its hardlink :: {}", deleted, link.bright_blue());
+                }
+            } else {
+                println!("          {} its hardlink :: {}", deleted, link.bright_blue());
+            }
+        }
+        if all_removed {
+            removed_bytes += i.duplicate_group_bytes_each as u64;
+        }
+    }
+    match retained_file {
+        Some((retained_file, reason)) => {
+            println!(
+                "\n      Retained the file :: {} (reason: {})\n",
+                retained_file.bright_green(),
+                reason
+            );
+        }
+        None => {
+            for reference in &i.reference_list {
+                println!("\n      Retained the reference file :: {}", reference.bright_green());
+            }
+            println!();
+        }
+    }
+    removed_bytes
+}
+
+// Describes the space the files which went take. Only deleting them reclaims it right away, while the trash and the
+// quarantine still hold the files until they are emptied or purged
+fn describe_space(disposal: &Disposal, bytes: u64, dry_run: bool) -> String {
+    let bytes = human_bytes(bytes as f64).bright_purple().bold();
+    let (done, to_do) = match disposal {
+        Disposal::Delete => ("reclaimed by deleting the duplicates", "which would be reclaimed by deleting the duplicates"),
+        Disposal::Trash => (
+            "moved to the trash, which is reclaimed once the trash is emptied",
+            "which would be moved to the trash",
+        ),
+        Disposal::Quarantine(_) => (
+            "moved to the quarantine, which is reclaimed once it is purged",
+            "which would be moved to the quarantine",
+        ),
+    };
+    format!("Space {}: {}", if dry_run { to_do } else { done }, bytes)
+}
+
+// Deletes a file, or moves it to the trash, which writes the `.trashinfo` file the file managers restore it from, or to
+// the quarantine, which records it in the manifest along with its checksum
+fn remove(path: &str, digest: &Digest, disposal: &mut Disposal) -> Result<(), String> {
+    match disposal {
+        Disposal::Delete => remove_file(path).map_err(|e| e.to_string()),
+        Disposal::Trash => trash::delete(path).map_err(|e| e.to_string()),
+        Disposal::Quarantine(quarantine) => quarantine.add(path, digest).map_err(|e| e.to_string()),
+    }
+}
+
+/// Leaves out the files of a group which changed since the report was written, and returns the current checksums of
+/// the files left
+pub fn verify_group(
+    group: &mut PrinterJSONObject,
+    algorithm: HashAlgorithm,
+    stale_files: &mut Vec<StaleFile>,
+) -> HashMap<String, Digest> {
+    let mut digests = HashMap::new();
+    for list in [&mut group.duplicate_list, &mut group.reference_list] {
+        list.retain(|x| {
+            // The files of a group which was not matched on their size have a size of their own
+            let size = group.sizes.get(x).copied().unwrap_or(group.duplicate_group_bytes_each as u64);
+            match verify(x, size, group.checksum.as_deref(), &group.modified, algorithm) {
+                Ok(digest) => {
+                    digests.insert(x.clone(), digest);
+                    true
+                }
+                Err(reason) => {
+                    stale_files.push(StaleFile { path: x.clone(), reason });
+                    false
+                }
+            }
+        });
+    }
+    digests
+}
+
+/// Prints the files which were left alone as they changed since the report was written
+pub fn print_stale_files(stale_files: &[StaleFile]) {
+    if !stale_files.is_empty() {
+        println!("## {} ##\n", "Stale: These files changed since the report was written, so they were left alone".bright_yellow().bold());
+        for i in stale_files {
+            println!("{} :: {}", i.path.bright_yellow(), i.reason);
+        }
+        println!();
+    }
+}
+
+// Checks that a file still has the size, the modified time and the checksum recorded in the report, and returns its
+// current checksum. Reports written by older versions lack the modified times, and reports of a hunt without `-c` lack
+// the checksums, so these are only checked when present. Otherwise the reason the file is stale is returned
+fn verify(
+    path: &str,
+    size: u64,
+    checksum: Option<&str>,
+    modified: &BTreeMap<String, String>,
+    algorithm: HashAlgorithm,
+) -> Result<Digest, String> {
+    let metadata = fs::metadata(path).map_err(|e| format!("it cannot be read due to {}", e))?;
+    if !metadata.is_file() {
+        return Err("it is no longer a file".to_owned());
+    }
+    if metadata.len() != size {
+        return Err(format!("its size changed from {} to {} bytes", size, metadata.len()));
+    }
+    if let Some(recorded) = modified.get(path) {
+        let current = metadata
+            .modified()
+            .map(|x| humantime::format_rfc3339_nanos(x).to_string())
+            .unwrap_or_default();
+        if *recorded != current {
+            return Err(format!("it was modified at {}, but the report recorded {}", current, recorded));
+        }
+    }
+    let digest = full_checksum(Path::new(path), algorithm).map_err(|e| format!("it cannot be read due to {}", e))?;
+    if checksum.is_some_and(|x| x != digest.to_string()) {
+        return Err("its content changed".to_owned());
+    }
+    Ok(digest)
+}
+
+/// Describes the policies which choose the file to keep, in the order they are applied
+pub fn describe_policies(policies: &[KeepPolicy]) -> String {
+    if policies.is_empty() {
+        "the last file of each group".to_owned()
+    } else {
+        policies.iter().map(|x| x.to_string()).collect::<Vec<_>>().join(", then ")
+    }
+}
+
+/// Takes the file to keep out of the files of a group. Each policy in turn narrows the files down to those it ranks best,
+/// and the last of the files left is kept. The policies which narrowed the files down are returned as the reason
+pub fn choose_keeper(files: &mut Vec<String>, policies: &[KeepPolicy], source_dirs: &[String]) -> Option<(String, String)> {
+    let mut candidates: Vec<usize> = (0..files.len()).collect();
+    let mut reasons = Vec::new();
+
+    for policy in policies {
+        if candidates.len() < 2 {
+            break;
+        }
+        let ranks: Vec<Option<i128>> = candidates.iter().map(|&x| rank(policy, &files[x], source_dirs)).collect();
+        if let Some(best) = ranks.iter().flatten().min().copied() {
+            let narrowed: Vec<usize> = candidates
+                .iter()
+                .zip(&ranks)
+                .filter(|(_, x)| **x == Some(best))
+                .map(|(x, _)| *x)
+                .collect();
+            if narrowed.len() < candidates.len() {
+                reasons.push(policy.to_string());
+                candidates = narrowed;
+            }
+        }
+    }
+
+    if candidates.len() > 1 {
+        reasons.push("last in the report".to_owned());
+    } else if reasons.is_empty() {
+        reasons.push("the only file left".to_owned());
+    }
+
+    let keeper = *candidates.last()?;
+    Some((files.remove(keeper), reasons.join(", then ")))
+}
+
+// Ranks a file by a policy, the lower the better. None when the policy cannot rank the file, like when its modified
+// time cannot be read, and such a file is never preferred
+fn rank(policy: &KeepPolicy, path: &str, source_dirs: &[String]) -> Option<i128> {
+    let modified = || {
+        fs::metadata(path)
+            .and_then(|x| x.modified())
+            .ok()
+            .and_then(|x| x.duration_since(UNIX_EPOCH).ok())
+            .map(|x| x.as_nanos() as i128)
+    };
+
+    match policy {
+        KeepPolicy::Oldest => modified(),
+        KeepPolicy::Newest => modified().map(|x| -x),
+        KeepPolicy::ShortestPath => Some(path.chars().count() as i128),
+        KeepPolicy::LongestPath => Some(-(path.chars().count() as i128)),
+        KeepPolicy::FirstRoot => source_dirs.iter().position(|x| Path::new(path).starts_with(x)).map(|x| x as i128),
+        KeepPolicy::PathMatches(pattern) => Some(if pattern.is_match(path) { 0 } else { 1 }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs::File,
+        time::{Duration, SystemTime},
+    };
+
+    use regex::Regex;
+    use tempfile::TempDir;
+
+    use super::*;
+
+    fn options(dry_run: bool, keep: Vec<KeepPolicy>) -> DeleteOptions {
+        DeleteOptions {
+            input_file: String::new(),
+            dry_run,
+            keep,
+            trash: false,
+            no_trash: true,
+            quarantine: None,
+        }
+    }
+
+    fn files(paths: &[&str]) -> Vec<String> {
+        paths.iter().map(|x| x.to_string()).collect()
+    }
+
+    // Writes a file with the given content and modified time, and returns its path
+    fn write_file(dir: &TempDir, name: &str, content: &str, modified: u64) -> String {
+        let path = dir.path().join(name);
+        fs::write(&path, content).unwrap();
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(modified))
+            .unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    fn group(files: &[String], bytes_each: usize) -> PrinterJSONObject {
+        serde_json::from_value(serde_json::json!({
+            "duplicate_group_no": 1,
+            "duplicate_group_count": files.len(),
+            "duplicate_group_bytes_each": bytes_each,
+            "duplicate_list": files,
+        }))
+        .unwrap()
+    }
+
+    fn modified(path: &str) -> String {
+        humantime::format_rfc3339_nanos(fs::metadata(path).unwrap().modified().unwrap()).to_string()
+    }
+
+    #[test]
+    fn verify_finds_the_changed_files() {
+        let dir = TempDir::new().unwrap();
+        let (a, b, c, d) = (
+            write_file(&dir, "a", "same", 1_000),
+            write_file(&dir, "b", "same", 1_000),
+            write_file(&dir, "c", "same", 1_000),
+            write_file(&dir, "d", "same", 1_000),
+        );
+        let checksum = full_checksum(Path::new(&a), HashAlgorithm::Md5).unwrap().to_string();
+        let mut group = group(&[a.clone(), b.clone(), c.clone(), d.clone()], 4);
+        group.checksum = Some(checksum);
+        group.modified = [&a, &b, &c, &d].iter().map(|x| (x.to_string(), modified(x))).collect();
+
+        write_file(&dir, "a", "longer", 1_000);
+        write_file(&dir, "b", "same", 2_000);
+        write_file(&dir, "c", "diff", 1_000);
+        fs::remove_file(&d).unwrap();
+        let mut stale_files = Vec::new();
+        let digests = verify_group(&mut group, HashAlgorithm::Md5, &mut stale_files);
+
+        assert!(group.duplicate_list.is_empty() && digests.is_empty());
+        let reasons: HashMap<&str, &str> = stale_files.iter().map(|x| (x.path.as_str(), x.reason.as_str())).collect();
+        assert_eq!(reasons[a.as_str()], "its size changed from 4 to 6 bytes");
+        assert!(reasons[b.as_str()].starts_with("it was modified at 1970-01-01T00:33:20"));
+        assert_eq!(reasons[c.as_str()], "its content changed");
+        assert!(reasons[d.as_str()].starts_with("it cannot be read"));
+    }
+
+    #[test]
+    fn verify_accepts_legacy_reports_and_own_sizes() {
+        let dir = TempDir::new().unwrap();
+        let (a, b) = (write_file(&dir, "a", "one", 1_000), write_file(&dir, "b", "three", 2_000));
+
+        // Reports written by older versions record neither the modified times nor the checksum
+        let copy = write_file(&dir, "copy", "one", 3_000);
+        let mut legacy = group(&[a.clone(), copy], 3);
+        let mut stale_files = Vec::new();
+        verify_group(&mut legacy, HashAlgorithm::Md5, &mut stale_files);
+        assert!(stale_files.is_empty());
+        assert_eq!(legacy.duplicate_list.len(), 2);
+
+        // The files of a group not matched on their size are checked against their own size
+        let mut unmatched = group(&[a.clone(), b.clone()], 5);
+        unmatched.size_not_matched = true;
+        unmatched.sizes = [(a.clone(), 3), (b.clone(), 5)].into_iter().collect();
+        verify_group(&mut unmatched, HashAlgorithm::Md5, &mut stale_files);
+        assert!(stale_files.is_empty());
+        assert_eq!(unmatched.duplicate_list, vec![a, b]);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn stale_hardlink_is_left_alone() {
+        let dir = TempDir::new().unwrap();
+        let (a, b) = (write_file(&dir, "a", "same", 1_000), write_file(&dir, "b", "same", 1_000));
+        let (linked, unlinked) = (dir.path().join("b-linked"), dir.path().join("b-unlinked"));
+        fs::hard_link(&b, &linked).unwrap();
+        fs::copy(&b, &unlinked).unwrap();
+        let (linked, unlinked) = (linked.to_string_lossy().into_owned(), unlinked.to_string_lossy().into_owned());
+        let mut group = group(&[b.clone(), a.clone()], 4);
+        group.hardlinks = [(b.clone(), vec![linked.clone(), unlinked.clone()])].into_iter().collect();
+        let (mut stale_files, mut failed) = (Vec::new(), Vec::new());
+
+        let removed_bytes = delete_group(
+            group,
+            &options(false, Vec::new()),
+            &[],
+            HashAlgorithm::Md5,
+            &mut Disposal::Delete,
+            &mut stale_files,
+            &mut failed,
+        );
+
+        assert!(failed.is_empty());
+        assert_eq!(removed_bytes, 4);
+        assert_eq!(stale_files.len(), 1);
+        assert_eq!(stale_files[0].path, unlinked);
+        assert_eq!(stale_files[0].reason, format!("it is no longer a hardlink of {}", b));
+        assert!(Path::new(&a).exists() && Path::new(&unlinked).exists());
+        assert!(!Path::new(&b).exists() && !Path::new(&linked).exists());
+    }
+
+    #[test]
+    fn rank_orders_by_each_policy() {
+        let dir = TempDir::new().unwrap();
+        let path = write_file(&dir, "a", "a", 1_000);
+        let roots = files(&["/other", dir.path().to_str().unwrap()]);
+
+        assert_eq!(rank(&KeepPolicy::Oldest, &path, &roots), Some(1_000_000_000_000));
+        assert_eq!(rank(&KeepPolicy::Newest, &path, &roots), Some(-1_000_000_000_000));
+        assert_eq!(rank(&KeepPolicy::ShortestPath, "/a/bc", &roots), Some(5));
+        assert_eq!(rank(&KeepPolicy::LongestPath, "/a/bc", &roots), Some(-5));
+        assert_eq!(rank(&KeepPolicy::FirstRoot, &path, &roots), Some(1));
+        assert_eq!(rank(&KeepPolicy::FirstRoot, "/elsewhere/a", &roots), None);
+        let pattern = KeepPolicy::PathMatches(Regex::new("^/keep/").unwrap());
+        assert_eq!(rank(&pattern, "/keep/a", &roots), Some(0));
+        assert_eq!(rank(&pattern, "/drop/a", &roots), Some(1));
+        // A file whose modified time cannot be read is not ranked
+        assert_eq!(rank(&KeepPolicy::Oldest, "/no/such/file", &roots), None);
+    }
+
+    #[test]
+    fn keeper_is_last_in_the_report_without_policies() {
+        let mut list = files(&["/a/1", "/a/2", "/a/3"]);
+        let keeper = choose_keeper(&mut list, &[], &[]);
+
+        assert_eq!(keeper, Some(("/a/3".to_owned(), "last in the report".to_owned())));
+        assert_eq!(list, files(&["/a/1", "/a/2"]));
+    }
+
+    #[test]
+    fn keeper_policies_break_the_ties_left_before_them() {
+        let dir = TempDir::new().unwrap();
+        let old = write_file(&dir, "old", "x", 1_000);
+        let also_old = write_file(&dir, "also-old", "x", 1_000);
+        let new = write_file(&dir, "new", "x", 2_000);
+
+        // The newest file is left out first, and the shorter path wins among the oldest ones
+        let mut list = vec![old.clone(), new.clone(), also_old.clone()];
+        let keeper = choose_keeper(&mut list, &[KeepPolicy::Oldest, KeepPolicy::ShortestPath], &[]);
+        assert_eq!(keeper, Some((old.clone(), "oldest, then shortest-path".to_owned())));
+
+        // A policy which ranks every file the same is left out of the reason
+        let mut list = vec![old.clone(), also_old.clone()];
+        let keeper = choose_keeper(&mut list, &[KeepPolicy::Oldest, KeepPolicy::LongestPath], &[]);
+        assert_eq!(keeper, Some((also_old, "longest-path".to_owned())));
+
+        // A tie left by all the policies falls back to the last file
+        let mut list = vec![old.clone(), new.clone()];
+        let keeper = choose_keeper(&mut list, &[KeepPolicy::ShortestPath], &[]);
+        assert_eq!(keeper, Some((new, "last in the report".to_owned())));
+    }
+
+    #[test]
+    fn keeper_by_path_pattern_and_root() {
+        let roots = files(&["/b", "/a"]);
+        let pattern = KeepPolicy::PathMatches(Regex::new("/keep/").unwrap());
+
+        let mut list = files(&["/a/keep/1", "/b/keep/1", "/b/drop/1"]);
+        let keeper = choose_keeper(&mut list, &[pattern.clone(), KeepPolicy::FirstRoot], &roots);
+        assert_eq!(keeper, Some(("/b/keep/1".to_owned(), "path-matches=/keep/, then first-root".to_owned())));
+
+        // A file outside every root is never preferred by first-root
+        let mut list = files(&["/a/1", "/c/1"]);
+        let keeper = choose_keeper(&mut list, &[KeepPolicy::FirstRoot], &roots);
+        assert_eq!(keeper, Some(("/a/1".to_owned(), "first-root".to_owned())));
+
+        // No file matching the pattern leaves the tie to the next policy
+        let mut list = files(&["/a/drop/1", "/b/drop/22"]);
+        let keeper = choose_keeper(&mut list, &[pattern, KeepPolicy::ShortestPath], &roots);
+        assert_eq!(keeper, Some(("/a/drop/1".to_owned(), "shortest-path".to_owned())));
+    }
+
+    #[test]
+    fn empty_group_keeps_one_file() {
+        let dir = TempDir::new().unwrap();
+        let list: Vec<String> = ["a", "b", "c"].iter().map(|x| write_file(&dir, x, "", 1_000)).collect();
+        let (mut stale_files, mut failed) = (Vec::new(), Vec::new());
+
+        delete_group(
+            group(&list, 0),
+            &options(false, vec![KeepPolicy::PathMatches(Regex::new("/a$").unwrap())]),
+            &[],
+            HashAlgorithm::Md5,
+            &mut Disposal::Delete,
+            &mut stale_files,
+            &mut failed,
+        );
+
+        assert!(stale_files.is_empty() && failed.is_empty());
+        assert!(Path::new(&list[0]).exists());
+        assert!(!Path::new(&list[1]).exists());
+        assert!(!Path::new(&list[2]).exists());
+    }
+
+    #[test]
+    fn dry_run_deletes_nothing() {
+        let dir = TempDir::new().unwrap();
+        for bytes_each in [0, 5] {
+            let content = "x".repeat(bytes_each);
+            let list: Vec<String> =
+                ["a", "b"].iter().map(|x| write_file(&dir, &format!("{}{}", x, bytes_each), &content, 1_000)).collect();
+            let (mut stale_files, mut failed) = (Vec::new(), Vec::new());
+
+            let removed_bytes = delete_group(
+                group(&list, bytes_each),
+                &options(true, Vec::new()),
+                &[],
+                HashAlgorithm::Md5,
+                &mut Disposal::Delete,
+                &mut stale_files,
+                &mut failed,
+            );
+
+            assert!(stale_files.is_empty() && failed.is_empty());
+            assert_eq!(removed_bytes, bytes_each as u64);
+            assert!(list.iter().all(|x| Path::new(x).exists()));
+        }
+    }
+
+    #[test]
+    fn trash_is_used_at_the_terminal_unless_asked_otherwise() {
+        let with_flags = |trash, no_trash| DeleteOptions {
+            trash,
+            no_trash,
+            ..options(false, Vec::new())
+        };
+
+        assert!(matches!(choose_disposal(&with_flags(false, false), true), Disposal::Trash));
+        assert!(matches!(choose_disposal(&with_flags(false, false), false), Disposal::Delete));
+        assert!(matches!(choose_disposal(&with_flags(true, false), false), Disposal::Trash));
+        assert!(matches!(choose_disposal(&with_flags(false, true), true), Disposal::Delete));
+    }
+
+    // The home trash is put inside the temporary directory, so it is on the same file system as the files and the
+    // trash of the user is left alone
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn trashed_files_are_moved_and_not_reclaimed() {
+        let dir = TempDir::new().unwrap();
+        std::env::set_var("XDG_DATA_HOME", dir.path().join("data"));
+        let list: Vec<String> = ["a", "b"].iter().map(|x| write_file(&dir, x, "trash me", 1_000)).collect();
+        let (mut stale_files, mut failed) = (Vec::new(), Vec::new());
+        let mut disposal = Disposal::Trash;
+
+        let moved_bytes = delete_group(
+            group(&list, 8),
+            &options(false, Vec::new()),
+            &[],
+            HashAlgorithm::Md5,
+            &mut disposal,
+            &mut stale_files,
+            &mut failed,
+        );
+
+        assert!(stale_files.is_empty() && failed.is_empty());
+        assert_eq!(moved_bytes, 8);
+        assert!(!Path::new(&list[0]).exists() && Path::new(&list[1]).exists());
+        assert_eq!(fs::read_to_string(dir.path().join("data/Trash/files/a")).unwrap(), "trash me");
+
+        let space = describe_space(&disposal, moved_bytes, false);
+        assert!(space.contains("moved to the trash") && !space.contains("reclaimed:"));
+    }
+
+    #[test]
+    fn only_deleting_reclaims_space() {
+        assert!(describe_space(&Disposal::Delete, 1024, false).starts_with("Space reclaimed by deleting the duplicates: "));
+        for dry_run in [false, true] {
+            let space = describe_space(&Disposal::Trash, 1024, dry_run);
+            assert!(space.contains("moved to the trash") && !space.contains("reclaimed:"));
+        }
+    }
+}
@@ -0,0 +1,245 @@
+// Copyright (c) 2024 Venkatesh Omkaram
+
+use clonehunter::common::{
+    config::HashAlgorithm,
+    core::{log, print_duplicates, CloneGroup, DuplicatesSummary, LogLevel, PrinterConfig, Stage},
+    filetype::is_one_of,
+};
+use hashbrown::{HashMap, HashSet};
+use std::{
+    ffi::OsString,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::hunt::{find_clones_by_checksum, split_references, HuntConfig};
+
+/// What a directory holds, summarised for its parent
+struct DirSummary {
+    digest: Vec<u8>,
+    total_bytes: u64,
+    file_count: usize,
+    /// Whether every entry under the directory was taken into account
+    complete: bool,
+}
+
+/// Hunts for identical directory trees. Every file is first matched with the regular checksum stages. Each group of
+/// identical files is given an id, and every other file is given an id of its own. A directory digest is then built
+/// bottom up from the names and ids of its files and the names and digests of its subdirectories, so two directories
+/// share a digest only when their whole trees are identical.
+///
+/// Directories inside an already matched tree are not reported again, and directories without any files are ignored, and
+/// so are the directories holding anything which the filters of the hunt left out.
+pub fn hunt_dirs(mut files: Vec<PathBuf>, dirs: Vec<PathBuf>, config: HuntConfig, print_config: PrinterConfig) -> DuplicatesSummary {
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(config.threads.into())
+        .build()
+        .unwrap();
+
+    log(LogLevel::INFO, "Generating hashes");
+
+    let (file_groups, stages) = find_clones_by_checksum(&pool, &files, &config);
+    // The files which are not of the types asked for count as left out by the filters. The types of the files which were
+    // hashed are already known
+    files.retain(|x| is_one_of(x, &config.types));
+
+    // Files which are identical share an id, the rest get a unique id
+    let mut file_ids: HashMap<&Path, u64> = HashMap::new();
+    for (id, group) in file_groups.iter().enumerate() {
+        for path in &group.paths {
+            file_ids.insert(path.as_path(), id as u64);
+        }
+    }
+    let mut next_id = file_groups.len() as u64;
+    for path in &files {
+        file_ids.entry(path.as_path()).or_insert_with(|| {
+            next_id += 1;
+            next_id
+        });
+    }
+
+    log(LogLevel::INFO, "Generating directory digests");
+
+    let summaries = summarise_dirs(&files, &file_ids, dirs, config.hash);
+
+    let mut dirs_by_digest: HashMap<Vec<u8>, Vec<(PathBuf, u64)>> = HashMap::new();
+    for (dir, digest, total_bytes) in summaries {
+        dirs_by_digest.entry(digest).or_default().push((dir, total_bytes));
+    }
+    dirs_by_digest.retain(|_, v| v.len() > 1);
+
+    // A directory whose parent was matched as well is already covered by the group of its parent. Such a group is
+    // only reported when it also has a copy outside the matched trees, and then a single nested copy is kept in it
+    let matched: HashSet<PathBuf> = dirs_by_digest.values().flatten().map(|x| x.0.clone()).collect();
+
+    let groups: Vec<CloneGroup> = dirs_by_digest
+        .into_values()
+        .filter_map(|v| {
+            let total_bytes = v[0].1;
+            let (mut nested, mut paths): (Vec<PathBuf>, Vec<PathBuf>) = v
+                .into_iter()
+                .map(|x| x.0)
+                .partition(|x| x.parent().is_some_and(|p| matched.contains(p)));
+            if paths.is_empty() {
+                return None;
+            }
+            nested.sort();
+            paths.extend(nested.into_iter().next());
+            paths.sort();
+            (paths.len() > 1).then(|| CloneGroup::new(total_bytes, paths, Stage::DirectoryTree, None))
+        })
+        .collect();
+    let groups = split_references(groups, &config.references);
+
+    log(LogLevel::INFO, format!("Found {} group(s) of identical directories", groups.len()).as_str());
+
+    print_duplicates(groups, stages, print_config)
+}
+
+// Builds the digest of every directory bottom up, and returns the directories holding files along with their digest and
+// the bytes of their files. A directory holding an entry which is neither among the files nor among the directories,
+// as the filters of the hunt left it out, is not known to be identical to any other, so it is left out along with
+// every directory above it
+fn summarise_dirs(
+    files: &[PathBuf],
+    file_ids: &HashMap<&Path, u64>,
+    dirs: Vec<PathBuf>,
+    hash: HashAlgorithm,
+) -> Vec<(PathBuf, Vec<u8>, u64)> {
+    let mut files_by_dir: HashMap<&Path, Vec<(OsString, u64, u64)>> = HashMap::new();
+    for path in files {
+        if let (Some(parent), Some(name)) = (path.parent(), path.file_name()) {
+            let size = path.metadata().map(|x| x.len()).unwrap_or_default();
+            files_by_dir
+                .entry(parent)
+                .or_default()
+                .push((name.to_owned(), file_ids[path.as_path()], size));
+        }
+    }
+
+    let mut dirs: Vec<PathBuf> = dirs.into_iter().collect::<HashSet<_>>().into_iter().collect();
+    // Deepest directories first, so that every subdirectory is summarised before its parent
+    dirs.sort_by_key(|x| std::cmp::Reverse(x.components().count()));
+
+    let mut subdirs_by_dir: HashMap<PathBuf, Vec<(OsString, DirSummary)>> = HashMap::new();
+    let mut summaries: Vec<(PathBuf, Vec<u8>, u64)> = Vec::new();
+
+    for dir in &dirs {
+        let mut hasher = hash.hasher();
+        let mut total_bytes = 0;
+        let mut file_count = 0;
+
+        let mut dir_files = files_by_dir.remove(dir.as_path()).unwrap_or_default();
+        let mut subdirs = subdirs_by_dir.remove(dir).unwrap_or_default();
+        let entries = fs::read_dir(dir).map(|x| x.count());
+        let mut complete = entries.is_ok_and(|x| x == dir_files.len() + subdirs.len());
+
+        dir_files.sort();
+        for (name, id, size) in dir_files {
+            hasher.update(b"f");
+            hasher.update(name.as_encoded_bytes());
+            hasher.update(&[0]);
+            hasher.update(&id.to_le_bytes());
+            total_bytes += size;
+            file_count += 1;
+        }
+
+        subdirs.sort_by(|a, b| a.0.cmp(&b.0));
+        for (name, summary) in subdirs {
+            hasher.update(b"d");
+            hasher.update(name.as_encoded_bytes());
+            hasher.update(&[0]);
+            hasher.update(&summary.digest);
+            total_bytes += summary.total_bytes;
+            file_count += summary.file_count;
+            complete &= summary.complete;
+        }
+
+        let digest = hasher.finish().0;
+
+        if let (Some(parent), Some(name)) = (dir.parent(), dir.file_name()) {
+            subdirs_by_dir.entry(parent.to_path_buf()).or_default().push((
+                name.to_owned(),
+                DirSummary {
+                    digest: digest.clone(),
+                    total_bytes,
+                    file_count,
+                    complete,
+                },
+            ));
+        }
+
+        if file_count > 0 && complete {
+            summaries.push((dir.clone(), digest, total_bytes));
+        }
+    }
+
+    summaries
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    // Writes the files under the root, and returns the files the hunt kept along with every directory
+    fn tree(root: &TempDir, files: &[(&str, &str)], kept: impl Fn(&str) -> bool) -> (Vec<PathBuf>, Vec<PathBuf>) {
+        let mut dirs = vec![root.path().to_path_buf()];
+        let mut kept_files = Vec::new();
+        for (name, content) in files {
+            let path = root.path().join(name);
+            let parent = path.parent().unwrap().to_path_buf();
+            fs::create_dir_all(&parent).unwrap();
+            fs::write(&path, content).unwrap();
+            if !dirs.contains(&parent) {
+                dirs.push(parent);
+            }
+            if kept(name) {
+                kept_files.push(path);
+            }
+        }
+        (kept_files, dirs)
+    }
+
+    // Gives the files with the same content the same id, like the checksum stages do
+    fn ids(files: &[PathBuf]) -> HashMap<&Path, u64> {
+        files.iter().map(|x| (x.as_path(), fxhash::hash64(&fs::read(x).unwrap()))).collect()
+    }
+
+    fn digest_of(summaries: &[(PathBuf, Vec<u8>, u64)], dir: &Path) -> Option<Vec<u8>> {
+        summaries.iter().find(|x| x.0 == dir).map(|x| x.1.clone())
+    }
+
+    #[test]
+    fn identical_trees_share_a_digest() {
+        let root = TempDir::new().unwrap();
+        let (files, dirs) = tree(
+            &root,
+            &[("A/x.txt", "x"), ("A/sub/y.txt", "y"), ("B/x.txt", "x"), ("B/sub/y.txt", "y"), ("C/x.txt", "x")],
+            |_| true,
+        );
+
+        let summaries = summarise_dirs(&files, &ids(&files), dirs, HashAlgorithm::Md5);
+
+        let (a, b, c) = (root.path().join("A"), root.path().join("B"), root.path().join("C"));
+        assert!(digest_of(&summaries, &a).is_some());
+        assert_eq!(digest_of(&summaries, &a), digest_of(&summaries, &b));
+        assert_ne!(digest_of(&summaries, &a), digest_of(&summaries, &c));
+    }
+
+    #[test]
+    fn trees_differing_only_in_a_filtered_file_are_not_identical() {
+        let root = TempDir::new().unwrap();
+        let (files, dirs) = tree(
+            &root,
+            &[("A/x.txt", "x"), ("A/sub/photo.jpg", "one"), ("B/x.txt", "x"), ("B/sub/photo.jpg", "two")],
+            |x| x.ends_with(".txt"),
+        );
+
+        let summaries = summarise_dirs(&files, &ids(&files), dirs, HashAlgorithm::Md5);
+
+        // The directories holding the filtered files are left out, and so is everything above them
+        assert!(summaries.is_empty());
+    }
+}
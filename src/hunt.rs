@@ -1,19 +1,18 @@
 // Copyright (c) 2024 Venkatesh Omkaram
 
-use clonehunter::{common::core::{log, print_duplicates, FileMetaData, LogLevel, PrinterConfig}, logger};
+use clonehunter::{common::{archive::{read_member, scan_archive, ArchiveIndex, is_archive}, cache::{ChecksumKind, HashCache}, config::{HardlinkMode, HashAlgorithm, MatchCriterion}, core::{file_identity, log, print_duplicates, CloneGroup, DuplicatesSummary, FileMetaData, LogLevel, PrinterConfig, Stage, StageStats}, filetype::{is_one_of, record_head}, hasher::{full_checksum, partial_checksum_with_head, Digest, PARTIAL_CHECKSUM_LIMIT, READ_BUFFER_SIZE}}, logger};
 use fxhash::FxHasher64;
-use hashbrown::HashMap;
+use hashbrown::{HashMap, HashSet};
 use indicatif::{ProgressBar, ProgressState, ProgressStyle};
-use md5::compute;
-use num_bigint::BigUint;
-use rayon::iter::{IntoParallelRefMutIterator, ParallelIterator};
-use std::{ffi::OsString, path::Path };
+use rayon::{iter::{IntoParallelRefIterator, IntoParallelRefMutIterator, ParallelIterator}, ThreadPool};
+use std::{path::Path, time::{Duration, SystemTime}};
 use std::sync::Mutex;
 use std::{
+    collections::VecDeque,
     fmt::Write,
     fs::File,
     hash::{Hash, Hasher},
-    io::{BufReader, Read},
+    io::{self, BufReader, Read},
     path::PathBuf,
     sync::Arc,
 };
@@ -25,6 +24,34 @@ use std::os::unix::fs::MetadataExt;
 #[cfg(target_os = "windows")]
 use std::os::windows::fs::MetadataExt;
 
+/// A file hunted by its metadata, with its modified time and size
+type MetadataEntry = (PathBuf, SystemTime, u64);
+
+/// Options which decide how the hunt is performed
+pub struct HuntConfig {
+    pub checksum: bool,
+    pub byte_compare: bool,
+    pub hash: HashAlgorithm,
+    /// The on-disk checksum cache, unless it was turned off with `--no-cache`
+    pub cache: Option<HashCache>,
+    pub hardlinks: HardlinkMode,
+    /// Canonical reference directories given with `--reference`
+    pub references: Vec<PathBuf>,
+    /// Keep the files which have no clone instead of the clones
+    pub unique: bool,
+    /// The file properties the clones must share when no checksum is made
+    pub match_on: Vec<MatchCriterion>,
+    /// Hunt among the members of the zip and tar archives as well
+    pub scan_archives: bool,
+    /// The members of the scanned archives. Filled in by the hunt
+    pub archives: ArchiveIndex,
+    /// Kinds, extensions or MIME types the detected type of a file must be one of. Empty when every type is accepted.
+    /// The checksum stages check them once the partial checksums are made, so the types are detected from the bytes
+    /// which were read for these
+    pub types: Vec<String>,
+    pub threads: u8,
+}
+
 /// The working of this function is very straightforward. It takes a List of File paths from the main function
 /// Instantiates a Progress bar, creates a ThreadPool and check if checksum is true or false.
 /// If the checksum is false, it means we won't need to do a checksum to hunt for duplicate files. Based on the file_name,
@@ -34,37 +61,40 @@ use std::os::windows::fs::MetadataExt;
 ///
 ///
 /// The only difference between no checksum and checksum is, in checksum, we will do some additional steps to ensure if the files are
-/// truly duplicate. No checksum is easy and fast, but using the checksum feature is reliable. Also, the checksum feature is not
-/// going to calculate the checksum of each file to the end of file. Instead, it will only generate a checksum based on first few thousand
-/// and last few thousand bytes. This makes it fast and not resource hungry.
-pub fn hunt(paths: Vec<PathBuf>, checksum: bool, threads: u8, print_config: PrinterConfig) -> (u64, u64) {
-    let list_hashes: Arc<Mutex<Vec<(BigUint, &std::path::Path)>>> =
-        Arc::new(Mutex::new(Vec::new()));
-
-    let list_hashes_caps: Arc<Mutex<HashMap<BigUint, u64>>> = Arc::new(Mutex::new(HashMap::new()));
-    let pb = Arc::new(Mutex::new(ProgressBar::new(paths.len() as u64)));
+/// truly duplicate. No checksum is easy and fast, but using the checksum feature is reliable. The checksum feature works in stages.
+/// First, it generates a checksum based on the first few thousand and last few thousand bytes along with the file length.
+/// Only the files which share such a partial checksum with another file are then hashed to the end of the file, and optionally
+/// compared byte by byte. This way the expensive full reads are only spent on the likely clones.
+pub fn hunt(paths: Vec<PathBuf>, mut config: HuntConfig, print_config: PrinterConfig) -> DuplicatesSummary {
+    // Hardlinks of the same file are hashed only once, and attached back to their groups before printing
+    let (mut paths, mut hardlinks) = match config.hardlinks {
+        HardlinkMode::Duplicates => (paths, HashMap::new()),
+        HardlinkMode::Collapse => collapse_hardlinks(paths),
+        HardlinkMode::Ignore => (collapse_hardlinks(paths).0, HashMap::new()),
+    };
+
+    if config.scan_archives {
+        config.archives = index_archives(&paths, &config);
+        paths.extend(config.archives.paths().cloned());
+    }
 
-    let mut hashmap_for_duplicates_meta: Arc<Mutex<HashMap<u64, Vec<OsString>>>> =
-        Arc::new(Mutex::new(HashMap::new()));
+    let pb = Arc::new(Mutex::new(ProgressBar::new(paths.len() as u64)));
 
-    let hashmap_for_duplicates_meta_caps: Arc<Mutex<HashMap<u64, u64>>> =
+    let hashmap_for_duplicates_meta: Arc<Mutex<HashMap<u64, Vec<MetadataEntry>>>> =
         Arc::new(Mutex::new(HashMap::new()));
 
-    pb.lock().unwrap().set_style(ProgressStyle::with_template("{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {pos} /{percent}% hashes completed ({eta_precise}) {msg}")
-    .unwrap()
-    .with_key("eta", |state: &ProgressState, w: &mut dyn Write| write!(w, "{:.1}s", state.eta().as_secs_f64()).unwrap())
-    .progress_chars("#>-"));
+    pb.lock().unwrap().set_style(progress_style());
 
     log(LogLevel::INFO, "Generating hashes");
 
     let pb_increment: Arc<Mutex<u64>> = Arc::new(Mutex::new(1));
 
     let pool = rayon::ThreadPoolBuilder::new()
-        .num_threads(threads.into())
+        .num_threads(config.threads.into())
         .build()
         .unwrap();
 
-    if !checksum {
+    if !config.checksum {
         pool.install(|| {
             rayon::scope(|s| {
                 for path in &paths {
@@ -72,7 +102,7 @@ pub fn hunt(paths: Vec<PathBuf>, checksum: bool, threads: u8, print_config: Prin
                     let pb_increment = pb_increment.clone();
 
                     if let Some(file_name) = path.as_path().file_name() {
-                        if let Some(file_name) = file_name.to_str() {
+                        if file_name.to_str().is_some() {
                             let modified_date = if let Ok(modified_date) = path.metadata() {
                                 if let Ok(system_time) = modified_date.modified() {
                                     system_time
@@ -98,8 +128,7 @@ pub fn hunt(paths: Vec<PathBuf>, checksum: bool, threads: u8, print_config: Prin
                             };
 
                             let hashmap_for_duplicates_meta = hashmap_for_duplicates_meta.clone();
-                            let hashmap_for_duplicates_meta_caps =
-                                hashmap_for_duplicates_meta_caps.clone();
+                            let match_on = &config.match_on;
 
                             s.spawn(move |_| {
                                 let pb = pb.clone();
@@ -107,47 +136,21 @@ pub fn hunt(paths: Vec<PathBuf>, checksum: bool, threads: u8, print_config: Prin
                                     .unwrap()
                                     .set_position(*pb_increment.lock().unwrap());
                                 *pb_increment.lock().unwrap() += 1;
-                                let duplicates_by_metadata = FileMetaData {
-                                    file_name,
-                                    modified_date,
-                                    file_size,
-                                };
+                                let duplicates_by_metadata = FileMetaData::new(path, file_size, match_on);
 
                                 let mut file_metadata_hasher = FxHasher64::default();
                                 duplicates_by_metadata.hash(&mut file_metadata_hasher);
 
                                 let hash_u64: u64 = file_metadata_hasher.finish();
-                                hashmap_for_duplicates_meta_caps
-                                    .lock()
-                                    .unwrap()
-                                    .insert(hash_u64, file_size);
 
                                 logger!("hash {:?} -> file {:?}", hash_u64, path);
 
-                                if hashmap_for_duplicates_meta
+                                hashmap_for_duplicates_meta
                                     .lock()
                                     .unwrap()
-                                    .contains_key(&hash_u64)
-                                {
-                                    let mut path_vec = hashmap_for_duplicates_meta
-                                        .lock()
-                                        .unwrap()
-                                        .get(&hash_u64)
-                                        .unwrap()
-                                        .to_owned();
-
-                                    path_vec.push(path.to_owned().into_os_string());
-
-                                    hashmap_for_duplicates_meta
-                                        .lock()
-                                        .unwrap()
-                                        .insert(hash_u64, path_vec);
-                                } else {
-                                    hashmap_for_duplicates_meta
-                                        .lock()
-                                        .unwrap()
-                                        .insert(hash_u64, vec![path.to_owned().into_os_string()]);
-                                }
+                                    .entry(hash_u64)
+                                    .or_default()
+                                    .push((path.to_owned(), modified_date, file_size));
                             });
                         }
                     }
@@ -159,116 +162,497 @@ pub fn hunt(paths: Vec<PathBuf>, checksum: bool, threads: u8, print_config: Prin
 
         log(LogLevel::INFO, "Finding clones");
 
-        print_duplicates(
-            &mut hashmap_for_duplicates_meta,
-            &hashmap_for_duplicates_meta_caps,
-            print_config,
-        )
+        let size_matched = config.match_on.iter().any(|x| matches!(x, MatchCriterion::Size));
+        let tolerance = config.match_on.iter().find_map(|x| match x {
+            MatchCriterion::Mtime(tolerance) => Some(*tolerance),
+            _ => None,
+        });
+        let mut groups: Vec<CloneGroup> = hashmap_for_duplicates_meta
+            .lock()
+            .unwrap()
+            .drain()
+            .flat_map(|(_, v)| split_by_mtime(v, tolerance, size_matched))
+            .collect();
+
+        attach_hardlinks(&mut groups, &mut hardlinks);
+        let mut groups = split_references(groups, &config.references);
+
+        if config.unique {
+            groups = unique_files(&paths, &groups, &mut hardlinks, &config, Stage::Metadata);
+        }
+
+        print_duplicates(groups, Vec::new(), print_config)
     } else {
-        pool.install(|| {
-            rayon::scope(|s| {
-                for path in &paths {
-                    let pb = pb.clone();
-                    let pb_increment = pb_increment.clone();
+        let (mut groups, stages) = find_clones_by_checksum(&pool, &paths, &config);
 
-                    let list_hashes = list_hashes.clone();
-                    let list_hashes_caps = list_hashes_caps.clone();
+        attach_hardlinks(&mut groups, &mut hardlinks);
+        let mut groups = split_references(groups, &config.references);
 
-                    // Spawn the threads here
-                    s.spawn(move |_| {
-                        let pb = pb.clone();
-                        pb.lock()
-                            .unwrap()
-                            .set_position(*pb_increment.lock().unwrap());
+        if config.unique {
+            let stage = if config.byte_compare { Stage::ByteCompare } else { Stage::FullHash };
+            groups = unique_files(&paths, &groups, &mut hardlinks, &config, stage);
+        }
 
-                        match File::open(path) {
-                            Ok(file) => {
-                                let mut reader = BufReader::new(file);
+        print_duplicates(groups, stages, print_config)
+    }
+}
 
-                                let file_length = path.metadata().unwrap().len();
+/// Runs the checksum stages over the paths and returns the groups of clones along with the statistics of each stage.
+/// The checksum cache is saved once the hashing is done
+pub fn find_clones_by_checksum(pool: &ThreadPool, paths: &[PathBuf], config: &HuntConfig) -> (Vec<CloneGroup>, Vec<StageStats>) {
+    let mut stages = Vec::new();
+
+    // Stage 1: Group the files by their size. A file whose size is not shared by any other file is never opened
+    let archives = &config.archives;
+    let candidates = group_by_size(pool, paths, archives);
+    stages.push(stage_stats(Stage::FileSize, paths.len(), candidates.len()));
+
+    // Stage 2: Partial checksums of the files which share their size with some other file
+    let hash = config.hash;
+    let cache = config.cache.as_ref();
+    let list_hashes = compute_hashes(pool, &candidates, &|path| checksum(archives, cache, path, hash, ChecksumKind::Partial), "partial checksums");
+    // The members of archives are not filtered by their type, like with the other filters of the walk
+    let list_hashes: Vec<(Digest, &Path)> = list_hashes
+        .into_iter()
+        .filter(|x| archives.get(x.1).is_some() || is_one_of(x.1, &config.types))
+        .collect();
+
+    for (i, k) in &list_hashes {
+        logger!("hash {} -> file {:?}", i, k);
+    }
 
-                                let hash_combine = if file_length > 2048 {
-                                    let mut buffer_front = vec![0; 1024];
-                                    let _ = reader.read_exact(&mut buffer_front);
+    let hashmap_group = sort_and_group_duplicates(list_hashes.as_slice());
 
-                                    let _ = reader.seek_relative(-1024);
+    let mut groups = Vec::new();
+    let mut survivors = Vec::new();
 
-                                    let mut buffer_back = vec![0; 1024];
-                                    let _ = reader.read_exact(&mut buffer_back);
+    for (k, v) in hashmap_group.lock().unwrap().drain().filter(|x| x.1.len() > 1) {
+        let file_length = file_length(archives, &v[0]);
 
-                                    compute(
-                                        [
-                                            buffer_front,
-                                            buffer_back,
-                                            file_length.to_le_bytes().to_vec(),
-                                        ]
-                                        .concat(),
-                                    )
-                                } else {
-                                    let mut buffer_full =
-                                        vec![
-                                            0;
-                                            <u64 as TryInto<usize>>::try_into(file_length).unwrap()
-                                        ];
-
-                                    let _ = reader.read_exact(&mut buffer_full);
-
-                                    compute(buffer_full)
-                                };
-
-                                let hash_to_bigint = hash_combine
-                                    .iter()
-                                    .map(|x| x.to_string())
-                                    .collect::<Vec<String>>()
-                                    .concat()
-                                    .parse::<BigUint>()
-                                    .unwrap();
-
-                                list_hashes
-                                    .lock()
-                                    .unwrap()
-                                    .push((hash_to_bigint.clone(), path.as_path()));
+        // Small files were already hashed in full during the partial checksum stage
+        if file_length <= PARTIAL_CHECKSUM_LIMIT {
+            groups.push(CloneGroup::new(file_length, v, Stage::FullHash, Some(k)));
+        } else {
+            survivors.extend(v);
+        }
+    }
 
-                                list_hashes_caps
-                                    .lock()
-                                    .unwrap()
-                                    .insert(hash_to_bigint, file_length);
-                            }
-                            Err(e) => log(LogLevel::ERROR, format!("File {:?} {:?}", path, e.kind()).as_str()),
-                        }
-                        *pb_increment.lock().unwrap() += 1;
-                    });
+    stages.push(stage_stats(Stage::PartialHash, candidates.len(), survivors.len() + count_files(&groups)));
+
+    // Stage 3: Full checksums of only those files which survived the partial checksum stage
+    if !survivors.is_empty() {
+        log(LogLevel::INFO, format!("Verifying {} candidate(s) with a full checksum", survivors.len()).as_str());
+
+        let list_hashes = compute_hashes(pool, &survivors, &|path| checksum(archives, cache, path, hash, ChecksumKind::Full), "full checksums");
+
+        for (i, k) in &list_hashes {
+            logger!("full hash {} -> file {:?}", i, k);
+        }
+
+        let hashmap_group = sort_and_group_duplicates(list_hashes.as_slice());
+
+        let mut confirmed = 0;
+        for (k, v) in hashmap_group.lock().unwrap().drain().filter(|x| x.1.len() > 1) {
+            confirmed += v.len();
+            groups.push(CloneGroup::new(file_length(archives, &v[0]), v, Stage::FullHash, Some(k)));
+        }
+
+        stages.push(stage_stats(Stage::FullHash, survivors.len(), confirmed));
+    }
+
+    if let Some(cache) = cache {
+        log(LogLevel::INFO, format!("Reused {} cached checksum(s), computed {}", cache.hits(), cache.misses()).as_str());
+        if let Err(e) = cache.save() {
+            log(LogLevel::ERROR, format!("Failed to save the hash cache due to {}", e).as_str());
+        }
+    }
+
+    // Stage 4: Optional byte by byte comparison of the confirmed groups
+    if config.byte_compare {
+        log(LogLevel::INFO, "Comparing the clones byte by byte");
+        let compared = count_files(&groups);
+        groups = pool.install(|| {
+            groups
+                .par_iter()
+                .flat_map_iter(|x| byte_compare_group(x, archives))
+                .filter(|x| x.paths.len() > 1)
+                .collect()
+        });
+        stages.push(stage_stats(Stage::ByteCompare, compared, count_files(&groups)));
+    }
+
+    (groups, stages)
+}
+
+pub fn progress_style() -> ProgressStyle {
+    ProgressStyle::with_template("{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {pos} /{percent}% hashes completed ({eta_precise}) {msg}")
+    .unwrap()
+    .with_key("eta", |state: &ProgressState, w: &mut dyn Write| write!(w, "{:.1}s", state.eta().as_secs_f64()).unwrap())
+    .progress_chars("#>-")
+}
+
+// Keeps a single path for every (device, inode) pair. The path which sorts first is kept, and the other
+// hardlinks are returned keyed by the kept path
+pub fn collapse_hardlinks(mut paths: Vec<PathBuf>) -> (Vec<PathBuf>, HashMap<PathBuf, Vec<PathBuf>>) {
+    paths.sort_unstable();
+
+    let mut kept: Vec<PathBuf> = Vec::with_capacity(paths.len());
+    let mut hardlinks: HashMap<PathBuf, Vec<PathBuf>> = HashMap::new();
+    let mut seen: HashMap<(u64, u64), usize> = HashMap::new();
+
+    for path in paths {
+        match path.metadata().ok().as_ref().and_then(file_identity) {
+            Some(identity) => match seen.get(&identity) {
+                Some(&i) => hardlinks.entry(kept[i].clone()).or_default().push(path),
+                None => {
+                    seen.insert(identity, kept.len());
+                    kept.push(path);
                 }
+            },
+            None => kept.push(path),
+        }
+    }
+
+    let collapsed: usize = hardlinks.values().map(|x| x.len()).sum();
+    if collapsed > 0 {
+        log(LogLevel::INFO, format!("Collapsed {} hardlink(s) into the files they link to", collapsed).as_str());
+    }
+
+    (kept, hardlinks)
+}
+
+// Moves the hardlinks of every grouped path into its group
+fn attach_hardlinks(groups: &mut [CloneGroup], hardlinks: &mut HashMap<PathBuf, Vec<PathBuf>>) {
+    for group in groups.iter_mut() {
+        for path in &group.paths {
+            if let Some(links) = hardlinks.remove(path) {
+                group.hardlinks.insert(path.clone(), links);
+            }
+        }
+    }
+}
+
+/// Moves the paths which lie under a reference directory to the references of their group, and keeps only the groups
+/// which have both a source and a reference copy. A source path with a hardlink under a reference directory is the
+/// reference file itself, so it is moved along with its hardlinks. Without reference directories the groups are returned as is
+pub fn split_references(groups: Vec<CloneGroup>, references: &[PathBuf]) -> Vec<CloneGroup> {
+    if references.is_empty() {
+        return groups;
+    }
+
+    let is_reference = |path: &Path| references.iter().any(|x| path.starts_with(x));
+
+    groups
+        .into_iter()
+        .filter_map(|mut group| {
+            let (refs, paths): (Vec<PathBuf>, Vec<PathBuf>) = group.paths.drain(..).partition(|path| {
+                is_reference(path) || group.hardlinks.get(path).into_iter().flatten().any(|x| is_reference(x))
             });
+            group.paths = paths;
+            group.references = refs;
+            (!group.paths.is_empty() && !group.references.is_empty()).then_some(group)
+        })
+        .collect()
+}
+
+// Makes a single file group of every path which is not in a group of clones. Files under the reference directories are
+// left out, so with references a file is unique when no reference directory holds a copy of it. So are the files which
+// are not of the types asked for, whose types are only detected here when they were not hashed
+fn unique_files(
+    paths: &[PathBuf],
+    groups: &[CloneGroup],
+    hardlinks: &mut HashMap<PathBuf, Vec<PathBuf>>,
+    config: &HuntConfig,
+    stage: Stage,
+) -> Vec<CloneGroup> {
+    let grouped: HashSet<&PathBuf> = groups
+        .iter()
+        .filter(|x| x.paths.len() + x.references.len() > 1)
+        .flat_map(|x| x.paths.iter().chain(x.references.iter()))
+        .collect();
+
+    paths
+        .iter()
+        .filter(|x| !grouped.contains(x) && !config.references.iter().any(|r| x.starts_with(r)))
+        .filter(|x| config.archives.get(x).is_some() || is_one_of(x, &config.types))
+        .map(|x| {
+            let mut group = CloneGroup::new(file_length(&config.archives, x), vec![x.clone()], stage, None);
+            if let Some(links) = hardlinks.remove(x) {
+                group.hardlinks.insert(x.clone(), links);
+            }
+            group
+        })
+        .collect()
+}
+
+// Buckets the paths by their file size and keeps only the buckets with two or more files
+fn group_by_size(pool: &ThreadPool, paths: &[PathBuf], archives: &ArchiveIndex) -> Vec<PathBuf> {
+    let sizes: Vec<(u64, &PathBuf)> = pool.install(|| {
+        paths
+            .par_iter()
+            .filter_map(|path| match archives.get(path).map(|x| x.size).ok_or(()).or_else(|_| path.metadata().map(|x| x.len())) {
+                Ok(size) => Some((size, path)),
+                Err(e) => {
+                    log(LogLevel::ERROR, format!("File {:?} {:?}", path, e.kind()).as_str());
+                    None
+                }
+            })
+            .collect()
+    });
+
+    let mut size_buckets: HashMap<u64, Vec<PathBuf>> = HashMap::new();
+    for (size, path) in sizes {
+        size_buckets.entry(size).or_default().push(path.to_path_buf());
+    }
+
+    size_buckets
+        .into_values()
+        .filter(|x| x.len() > 1)
+        .flatten()
+        .collect()
+}
+
+// Logs and returns how many files a stage has eliminated out of the files which entered it
+pub fn stage_stats(stage: Stage, candidates: usize, survivors: usize) -> StageStats {
+    let eliminated = candidates.saturating_sub(survivors);
+    log(
+        LogLevel::INFO,
+        format!("Stage '{}' eliminated {} of {} file(s)", stage, eliminated, candidates).as_str(),
+    );
+    StageStats {
+        stage,
+        candidates,
+        eliminated,
+    }
+}
+
+fn count_files(groups: &[CloneGroup]) -> usize {
+    groups.iter().map(|x| x.paths.len()).sum()
+}
+
+// Archive members were hashed while their archive was scanned. Otherwise the checksum is looked up in the cache when
+// there is one, or the file is hashed
+fn checksum(archives: &ArchiveIndex, cache: Option<&HashCache>, path: &Path, algorithm: HashAlgorithm, kind: ChecksumKind) -> io::Result<Digest> {
+    if let Some(member) = archives.get(path) {
+        return Ok(match kind {
+            ChecksumKind::Partial => member.partial.clone(),
+            ChecksumKind::Full => member.full.clone(),
         });
+    }
 
-        pb.lock().unwrap().finish_and_clear();
+    // The first bytes read for the partial checksum tell the type of the file as well
+    let partial = || {
+        partial_checksum_with_head(path, algorithm).map(|(digest, head)| {
+            record_head(path, &head);
+            digest
+        })
+    };
+
+    match (cache, kind) {
+        (Some(cache), ChecksumKind::Partial) => cache.checksum_with(path, algorithm, kind, partial),
+        (Some(cache), ChecksumKind::Full) => cache.checksum(path, algorithm, kind),
+        (None, ChecksumKind::Partial) => partial(),
+        (None, ChecksumKind::Full) => full_checksum(path, algorithm),
+    }
+}
+
+fn file_length(archives: &ArchiveIndex, path: &Path) -> u64 {
+    match archives.get(path) {
+        Some(member) => member.size,
+        None => path.metadata().map(|x| x.len()).unwrap_or_default(),
+    }
+}
+
+// Splits the files sharing a metadata key into groups whose modified times are within the tolerance of each other.
+// The files are sorted by their modified time and a new group starts at the first file modified more than the tolerance
+// after the first file of the group, so a chain of small gaps cannot stretch a group beyond the tolerance. Without a
+// tolerance the modified time is not matched at all. When the size is not matched either, the size of each file is kept
+fn split_by_mtime(mut entries: Vec<MetadataEntry>, tolerance: Option<Duration>, size_matched: bool) -> Vec<CloneGroup> {
+    let mut runs: Vec<Vec<MetadataEntry>> = Vec::new();
+
+    match tolerance {
+        None => runs.push(entries),
+        Some(tolerance) => {
+            entries.sort_by_key(|x| x.1);
+            for entry in entries {
+                match runs.last_mut() {
+                    Some(run) if entry.1.duration_since(run[0].1).unwrap_or_default() <= tolerance => {
+                        run.push(entry)
+                    }
+                    _ => runs.push(vec![entry]),
+                }
+            }
+        }
+    }
+
+    runs.into_iter()
+        .map(|run| {
+            let bytes_each = run.iter().map(|x| x.2).max().unwrap_or_default();
+            let sizes = if size_matched { HashMap::new() } else { run.iter().map(|x| (x.0.clone(), x.2)).collect() };
+            let paths = run.into_iter().map(|x| x.0).collect();
+            let mut group = CloneGroup::new(bytes_each, paths, Stage::Metadata, None);
+            group.sizes = sizes;
+            group
+        })
+        .collect()
+}
+
+// Reads the members of every archive among the paths in the thread pool. Archives which cannot be read are logged and skipped
+fn index_archives(paths: &[PathBuf], config: &HuntConfig) -> ArchiveIndex {
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(config.threads.into())
+        .build()
+        .unwrap();
+
+    let archives: Vec<&PathBuf> = paths.iter().filter(|x| is_archive(x)).collect();
+
+    log(LogLevel::INFO, format!("Scanning {} archive(s)", archives.len()).as_str());
+
+    let pb = ProgressBar::new(archives.len() as u64);
+    pb.set_style(progress_style());
+    pb.set_message("archives scanned");
+
+    let members: Vec<_> = pool.install(|| {
+        archives
+            .par_iter()
+            .flat_map_iter(|path| {
+                let members = scan_archive(path, config.hash).unwrap_or_else(|e| {
+                    log(LogLevel::ERROR, format!("Failed to read the archive {:?} due to {}", path, e).as_str());
+                    Vec::new()
+                });
+                pb.inc(1);
+                members
+            })
+            .collect()
+    });
+
+    pb.finish_and_clear();
+
+    let mut index = ArchiveIndex::default();
+    for (path, member) in members {
+        index.insert(path, member);
+    }
+
+    log(LogLevel::INFO, format!("Found {} file(s) inside the archives", index.len()).as_str());
+
+    index
+}
 
-        for (i, k) in &*list_hashes.clone().lock().unwrap() {
-            logger!("hash {:?} -> file {:?}", i, k);
+// Opens the content of a file or an archive member for reading
+fn open_content(archives: &ArchiveIndex, path: &Path) -> io::Result<Box<dyn Read>> {
+    if archives.get(path).is_some() {
+        Ok(Box::new(io::Cursor::new(read_member(path)?)))
+    } else {
+        Ok(Box::new(File::open(path)?))
+    }
+}
+
+// Runs the hash function over every path in the thread pool. Files which cannot be read are logged and left out
+fn compute_hashes<'a>(
+    pool: &ThreadPool,
+    paths: &'a [PathBuf],
+    hash_fn: &(dyn Fn(&Path) -> io::Result<Digest> + Sync),
+    message: &'static str,
+) -> Vec<(Digest, &'a Path)> {
+    let pb = ProgressBar::new(paths.len() as u64);
+    pb.set_style(progress_style());
+    pb.set_message(message);
+
+    let list_hashes = pool.install(|| {
+        paths
+            .par_iter()
+            .filter_map(|path| {
+                let result = match hash_fn(path) {
+                    Ok(hash) => Some((hash, path.as_path())),
+                    Err(e) => {
+                        log(LogLevel::ERROR, format!("File {:?} {:?}", path, e.kind()).as_str());
+                        None
+                    }
+                };
+                pb.inc(1);
+                result
+            })
+            .collect()
+    });
+
+    pb.finish_and_clear();
+
+    list_hashes
+}
+
+// Splits a group into the sets of files which are identical byte by byte.
+// Each file is compared against the first file of the remaining set, and the files which differ are compared among themselves
+fn byte_compare_group(group: &CloneGroup, archives: &ArchiveIndex) -> Vec<CloneGroup> {
+    let mut groups = Vec::new();
+    let mut remaining: VecDeque<PathBuf> = group.paths.iter().cloned().collect();
+
+    while let Some(first) = remaining.pop_front() {
+        let mut identical = vec![first.clone()];
+        let mut different = VecDeque::new();
+
+        for path in remaining.drain(..) {
+            match files_are_identical(archives, &first, &path) {
+                Ok(true) => identical.push(path),
+                Ok(false) => different.push_back(path),
+                Err(e) => log(LogLevel::ERROR, format!("File {:?} {:?}", path, e.kind()).as_str()),
+            }
         }
 
-        let mut hashmap_group = sort_and_group_duplicates(list_hashes.lock().unwrap().as_slice());
+        groups.push(CloneGroup::new(group.bytes_each, identical, Stage::ByteCompare, group.digest.clone()));
 
-        print_duplicates(&mut hashmap_group, &list_hashes_caps, print_config)
+        remaining = different;
     }
+
+    groups
 }
 
+fn files_are_identical(archives: &ArchiveIndex, a: &Path, b: &Path) -> io::Result<bool> {
+    if file_length(archives, a) != file_length(archives, b) {
+        return Ok(false);
+    }
+
+    let mut reader_a = BufReader::with_capacity(READ_BUFFER_SIZE, open_content(archives, a)?);
+    let mut reader_b = BufReader::with_capacity(READ_BUFFER_SIZE, open_content(archives, b)?);
+    let mut buffer_a = vec![0; READ_BUFFER_SIZE];
+    let mut buffer_b = vec![0; READ_BUFFER_SIZE];
+
+    loop {
+        let read = read_fully(&mut reader_a, &mut buffer_a)?;
+        if read != read_fully(&mut reader_b, &mut buffer_b)? || buffer_a[..read] != buffer_b[..read] {
+            return Ok(false);
+        }
+        if read == 0 {
+            return Ok(true);
+        }
+    }
+}
+
+// Fills the buffer as much as possible, so that two readers can be compared chunk by chunk
+fn read_fully<R: Read>(reader: &mut R, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut total = 0;
+    while total < buffer.len() {
+        match reader.read(&mut buffer[total..])? {
+            0 => break,
+            n => total += n,
+        }
+    }
+    Ok(total)
+}
 
 // This function helps in sorting the vec of Hash digest and filePath.
 // Once the sort is finished it will group Duplicates with the help of HashMap and Parallel Iterator
 pub fn sort_and_group_duplicates(
-    list_hashes: &[(BigUint, &Path)],
-) -> Arc<Mutex<HashMap<BigUint, Vec<PathBuf>>>> {
+    list_hashes: &[(Digest, &Path)],
+) -> Arc<Mutex<HashMap<Digest, Vec<PathBuf>>>> {
     let num_hashes_vec = Arc::new(Mutex::new(Vec::new()));
     let bar = ProgressBar::new(num_hashes_vec.lock().unwrap().len() as u64);
-    let hashmap_accumulator: Arc<Mutex<HashMap<BigUint, Vec<PathBuf>>>> =
+    let hashmap_accumulator: Arc<Mutex<HashMap<Digest, Vec<PathBuf>>>> =
         Arc::new(Mutex::new(HashMap::new()));
 
-    for (i, k) in list_hashes.into_iter() {
+    for (i, k) in list_hashes.iter() {
         num_hashes_vec.lock().unwrap().push(Grouper {
-            hash_to_bigint: i.to_owned(),
-            path_buf: k.to_owned().into(),
+            digest: i.to_owned(),
+            path_buf: k.to_path_buf(),
         });
     }
 
@@ -280,31 +664,59 @@ pub fn sort_and_group_duplicates(
 
     num_hashes_vec.par_iter_mut().for_each(|x| {
         let r = &x.path_buf;
-        let r1 = &x.hash_to_bigint;
-        if hashmap_accumulator.lock().unwrap().contains_key(r1) {
-            let mut new = hashmap_accumulator
-                .lock()
-                .unwrap()
-                .get(r1)
-                .unwrap()
-                .to_owned();
-            new.push(r.clone());
-            hashmap_accumulator.lock().unwrap().insert(r1.clone(), new);
-        } else {
-            hashmap_accumulator
-                .lock()
-                .unwrap()
-                .insert(r1.clone(), vec![r.clone()]);
-        }
+        let r1 = &x.digest;
+        hashmap_accumulator
+            .lock()
+            .unwrap()
+            .entry(r1.clone())
+            .or_default()
+            .push(r.clone());
         bar.inc(1);
     });
 
     hashmap_accumulator
 }
 
-// Used to store Hash Digest as BigInt and Path of Files for Sorting Operations
+// Used to store Hash Digest and Path of Files for Sorting Operations
 #[derive(Ord, PartialOrd, PartialEq, Eq, Debug)]
 struct Grouper {
-    hash_to_bigint: BigUint,
+    digest: Digest,
     path_buf: PathBuf,
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::UNIX_EPOCH;
+
+    use super::*;
+
+    fn entries(millis: &[u64]) -> Vec<MetadataEntry> {
+        millis
+            .iter()
+            .map(|x| (PathBuf::from(format!("/f{}", x)), UNIX_EPOCH + Duration::from_millis(*x), 10))
+            .collect()
+    }
+
+    fn paths(groups: &[CloneGroup]) -> Vec<Vec<PathBuf>> {
+        groups.iter().map(|x| x.paths.clone()).collect()
+    }
+
+    #[test]
+    fn groups_stay_within_the_tolerance() {
+        // Every gap is just under the tolerance, but the first and the last files are further apart
+        let groups = split_by_mtime(entries(&[0, 1_900, 3_800]), Some(Duration::from_secs(2)), true);
+
+        assert_eq!(
+            paths(&groups),
+            vec![vec![PathBuf::from("/f0"), PathBuf::from("/f1900")], vec![PathBuf::from("/f3800")]]
+        );
+    }
+
+    #[test]
+    fn modified_time_is_not_matched_without_a_tolerance() {
+        let groups = split_by_mtime(entries(&[0, 100_000]), None, false);
+
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].sizes.len(), 2);
+    }
+}
@@ -0,0 +1,233 @@
+// Copyright (c) 2024 Venkatesh Omkaram
+
+use clonehunter::common::{
+    config::ImageHash,
+    core::{log, print_duplicates, DuplicatesSummary, LogLevel, PrinterConfig, SimilarPair, Stage},
+};
+use hashbrown::HashMap;
+use image::{imageops::FilterType, ImageFormat, ImageReader};
+use indicatif::ProgressBar;
+use rayon::iter::{IndexedParallelIterator, IntoParallelRefIterator, ParallelIterator};
+use std::{
+    f64::consts::PI,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    hunt::{collapse_hardlinks, progress_style, split_references, stage_stats, HuntConfig},
+    similar::group_pairs,
+};
+
+/// Side of the thumbnail whose cosine transform gives the perceptual hash
+const PHASH_SIZE: usize = 32;
+
+/// Side of the block of low frequencies kept by the perceptual hash
+const PHASH_BLOCK: usize = 8;
+
+/// Hunts for images which look the same. Every image is decoded, shrunk to a small grayscale thumbnail and reduced to a
+/// 64 bit perceptual hash. The hashes are put in a BK-tree, so that each image only has to be compared with the images
+/// whose hashes could be within the distance. Images which are close to a common image end up in the same group.
+pub fn hunt_images(
+    files: Vec<PathBuf>,
+    algorithm: ImageHash,
+    max_distance: u32,
+    config: HuntConfig,
+    print_config: PrinterConfig,
+) -> DuplicatesSummary {
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(config.threads.into())
+        .build()
+        .unwrap();
+
+    // The hardlinks of an image are the very same image
+    let (files, _) = collapse_hardlinks(files);
+
+    let images: Vec<&PathBuf> = files
+        .iter()
+        .filter(|x| ImageFormat::from_path(x).is_ok_and(|x| x.reading_enabled()))
+        .collect();
+
+    log(LogLevel::INFO, format!("Hashing {} image(s) with {}", images.len(), algorithm).as_str());
+
+    let pb = ProgressBar::new(images.len() as u64);
+    pb.set_style(progress_style());
+    pb.set_message("images hashed");
+
+    let hashes: Vec<(u64, &Path)> = pool.install(|| {
+        images
+            .par_iter()
+            .filter_map(|path| {
+                let hash = match image_hash(path, algorithm) {
+                    Ok(hash) => Some((hash, path.as_path())),
+                    Err(e) => {
+                        log(LogLevel::ERROR, format!("File {:?} {}", path, e).as_str());
+                        None
+                    }
+                };
+                pb.inc(1);
+                hash
+            })
+            .collect()
+    });
+
+    pb.finish_and_clear();
+
+    log(LogLevel::INFO, "Comparing the image hashes");
+
+    let mut tree = BkTree::default();
+    for (i, (hash, _)) in hashes.iter().enumerate() {
+        tree.insert(*hash, i);
+    }
+
+    let pairs: Vec<SimilarPair> = pool.install(|| {
+        hashes
+            .par_iter()
+            .enumerate()
+            .flat_map_iter(|(i, (hash, path))| {
+                tree.find(*hash, max_distance)
+                    .into_iter()
+                    .filter(move |(j, _)| *j > i)
+                    .map(|(j, distance)| SimilarPair {
+                        a: path.to_path_buf(),
+                        b: hashes[j].1.to_path_buf(),
+                        score: 1.0 - distance as f64 / 64.0,
+                        shared_bytes: None,
+                    })
+            })
+            .collect()
+    });
+
+    let hash_by_path: HashMap<&Path, u64> = hashes.iter().map(|(hash, path)| (*path, *hash)).collect();
+
+    // Each image of a group reports its distance from the first image instead of the pairs
+    let mut groups = group_pairs(pairs, Stage::PerceptualHash);
+    for group in groups.iter_mut() {
+        group.pairs.clear();
+        let first = hash_by_path[group.first_path()];
+        group.distances = group
+            .paths
+            .iter()
+            .map(|x| (x.clone(), (hash_by_path[x.as_path()] ^ first).count_ones()))
+            .collect();
+    }
+    let groups = split_references(groups, &config.references);
+
+    let grouped: usize = groups.iter().map(|x| x.paths.len() + x.references.len()).sum();
+    let stages = vec![stage_stats(Stage::PerceptualHash, hashes.len(), grouped)];
+
+    print_duplicates(groups, stages, print_config)
+}
+
+// Decodes the image and computes its 64 bit perceptual hash
+fn image_hash(path: &Path, algorithm: ImageHash) -> Result<u64, image::ImageError> {
+    let image = ImageReader::open(path)?.with_guessed_format()?.decode()?;
+
+    let hash = match algorithm {
+        ImageHash::AHash => {
+            let pixels = image.resize_exact(8, 8, FilterType::Triangle).to_luma8().into_raw();
+            let mean = pixels.iter().map(|&x| x as u32).sum::<u32>() / pixels.len() as u32;
+            bits(pixels.iter().map(|&x| x as u32 > mean))
+        }
+        ImageHash::DHash => {
+            let pixels = image.resize_exact(9, 8, FilterType::Triangle).to_luma8();
+            bits((0..8).flat_map(|y| (0..8).map(move |x| (x, y))).map(|(x, y)| pixels.get_pixel(x, y)[0] > pixels.get_pixel(x + 1, y)[0]))
+        }
+        ImageHash::PHash => {
+            let pixels = image
+                .resize_exact(PHASH_SIZE as u32, PHASH_SIZE as u32, FilterType::Triangle)
+                .to_luma8()
+                .into_raw();
+            let coefficients = low_frequencies(&pixels);
+            // The first coefficient is the average brightness, so it is left out of the median
+            let mut sorted = coefficients[1..].to_vec();
+            sorted.sort_by(|a, b| a.total_cmp(b));
+            let median = sorted[sorted.len() / 2];
+            bits(coefficients.iter().map(|&x| x > median))
+        }
+    };
+
+    Ok(hash)
+}
+
+// The top left block of the two dimensional cosine transform of the thumbnail, row by row
+fn low_frequencies(pixels: &[u8]) -> Vec<f64> {
+    let cosine = |frequency: usize, position: usize| {
+        ((2 * position + 1) as f64 * frequency as f64 * PI / (2 * PHASH_SIZE) as f64).cos()
+    };
+
+    // Transform the rows first, keeping only the low frequencies, and then the columns
+    let mut rows = vec![[0.0; PHASH_BLOCK]; PHASH_SIZE];
+    for (y, row) in rows.iter_mut().enumerate() {
+        for (u, value) in row.iter_mut().enumerate() {
+            *value = (0..PHASH_SIZE).map(|x| pixels[y * PHASH_SIZE + x] as f64 * cosine(u, x)).sum();
+        }
+    }
+
+    (0..PHASH_BLOCK)
+        .flat_map(|v| (0..PHASH_BLOCK).map(move |u| (u, v)))
+        .map(|(u, v)| (0..PHASH_SIZE).map(|y| rows[y][u] * cosine(v, y)).sum())
+        .collect()
+}
+
+fn bits(values: impl Iterator<Item = bool>) -> u64 {
+    values.fold(0, |hash, bit| (hash << 1) | bit as u64)
+}
+
+/// A tree of hashes arranged by their Hamming distance from each other. Every child sits under the distance it has
+/// from its parent, so a search only descends into the children whose distance can still be within the limit
+#[derive(Default)]
+struct BkTree {
+    nodes: Vec<BkNode>,
+}
+
+struct BkNode {
+    hash: u64,
+    index: usize,
+    children: HashMap<u32, usize>,
+}
+
+impl BkTree {
+    fn insert(&mut self, hash: u64, index: usize) {
+        let new_node = self.nodes.len();
+        let mut current = 0;
+
+        if self.nodes.is_empty() {
+            self.nodes.push(BkNode { hash, index, children: HashMap::new() });
+            return;
+        }
+
+        loop {
+            let distance = (self.nodes[current].hash ^ hash).count_ones();
+            match self.nodes[current].children.get(&distance) {
+                Some(&child) => current = child,
+                None => {
+                    self.nodes[current].children.insert(distance, new_node);
+                    self.nodes.push(BkNode { hash, index, children: HashMap::new() });
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Returns the index and the distance of every hash within the maximum distance
+    fn find(&self, hash: u64, max_distance: u32) -> Vec<(usize, u32)> {
+        let mut found = Vec::new();
+        let mut pending = if self.nodes.is_empty() { vec![] } else { vec![0] };
+
+        while let Some(current) = pending.pop() {
+            let node = &self.nodes[current];
+            let distance = (node.hash ^ hash).count_ones();
+            if distance <= max_distance {
+                found.push((node.index, distance));
+            }
+            pending.extend(
+                node.children
+                    .iter()
+                    .filter(|(&d, _)| d + max_distance >= distance && d <= distance + max_distance)
+                    .map(|(_, &child)| child),
+            );
+        }
+
+        found
+    }
+}
@@ -0,0 +1,411 @@
+// Copyright (c) 2024 Venkatesh Omkaram
+
+use std::{
+    fs::{self, Metadata},
+    io,
+    path::Path,
+};
+
+#[cfg(target_os = "linux")]
+use std::os::unix::fs::MetadataExt;
+
+use clonehunter::common::{
+    archive::is_member,
+    config::{HashAlgorithm, LinkOptions},
+    core::{confirmation, file_identity, log, LogLevel, PrinterJSONObject, PrinterJSONReport, Stage},
+};
+use colored::Colorize;
+use hashbrown::HashSet;
+use human_bytes::human_bytes;
+
+use crate::delete::{choose_keeper, describe_policies, print_stale_files, verify_group, StaleFile};
+
+/// How many temporary names are tried for a hardlink before giving up
+const TEMP_NAME_ATTEMPTS: u32 = 100;
+
+/// Replaces the duplicates of each group with hardlinks to the file which is kept, so every path keeps existing while
+/// the content is stored once. The files are checked against the report first, like the `delete` command does, and a
+/// duplicate on another file system than the kept file, or with other permissions or another owner, is left alone.
+/// The groups with reference copies are skipped, as the reference copies are never touched
+pub fn link(report: PrinterJSONReport, options: &LinkOptions) {
+    let input_json = report.duplicate_groups;
+    let dry_run = options.dry_run;
+    // Reports written without a checksum are verified with the default algorithm
+    let algorithm = report.hash_algorithm.unwrap_or(HashAlgorithm::Md5);
+    let mut failed_to_link = Vec::new();
+    let mut stale_files = Vec::new();
+    let mut reclaimed_bytes = 0;
+    let tota_groups = input_json.len();
+
+    log(LogLevel::INFO, format!("Is this a dry run? : {}", dry_run.to_string().blink()).as_str());
+    log(LogLevel::INFO, format!("Keep policy : {}", describe_policies(&options.keep)).as_str());
+    log(LogLevel::INFO, format!("Found {} group(s)", tota_groups).as_str());
+
+    if tota_groups == 0 {
+        println!("\nFound no duplicates. You lucky son of a gun.\n");
+        return;
+    }
+
+    println!("{}", "\nShall I proceed to replace the duplicates with hardlinks?".bright_blue());
+    if confirmation() != "Y" {
+        println!("Phew... You QUIT!\n");
+        return;
+    }
+
+    for i in input_json {
+        reclaimed_bytes += link_group(i, options, &report.source_dirs, algorithm, &mut stale_files, &mut failed_to_link);
+    }
+
+    print_stale_files(&stale_files);
+
+    if !failed_to_link.is_empty() {
+        println!("## {} ##\n", "Error: Looks like some duplicates were not linked. Here is the list".bright_red().bold());
+        for i in &failed_to_link {
+            eprintln!("{}", i.bright_magenta());
+        }
+        println!();
+    }
+
+    log(
+        LogLevel::INFO,
+        format!(
+            "Space {} by linking the duplicates: {}",
+            if dry_run { "which would be reclaimed" } else { "reclaimed" },
+            human_bytes(reclaimed_bytes as f64).bright_purple().bold()
+        )
+        .as_str(),
+    );
+    if dry_run {
+        log(LogLevel::INFO, "Nothing changed. This was a dry run.\n");
+    }
+}
+
+// Replaces the duplicates of a group with hardlinks to the file which is kept, and returns the bytes freed
+fn link_group(
+    mut i: PrinterJSONObject,
+    options: &LinkOptions,
+    source_dirs: &[String],
+    algorithm: HashAlgorithm,
+    stale_files: &mut Vec<StaleFile>,
+    failed_to_link: &mut Vec<String>,
+) -> u64 {
+    let dry_run = options.dry_run;
+    if i.confirmed_by == Some(Stage::DirectoryTree) {
+        println!("Skipping group {} as it holds directories", i.duplicate_group_no);
+        return 0;
+    }
+    if i.confirmed_by.is_some_and(|x| !x.finds_copies()) {
+        println!("Skipping group {} as its files are only similar, not identical", i.duplicate_group_no);
+        return 0;
+    }
+    if i.size_not_matched {
+        println!(
+            "Skipping group {} as its files were not matched on their size, so they are not known to be copies",
+            i.duplicate_group_no
+        );
+        return 0;
+    }
+    // Linking the duplicates to a reference copy would tie them to a file which must never be touched
+    if !i.reference_list.is_empty() {
+        println!("Skipping group {} as it has reference copies, which are never linked to", i.duplicate_group_no);
+        return 0;
+    }
+    // Files inside archives cannot be linked
+    let (members, files): (Vec<String>, Vec<String>) =
+        i.duplicate_list.into_iter().partition(|x| is_member(Path::new(x)));
+    i.duplicate_list = files;
+    for member in &members {
+        println!("      Skipping the file inside an archive :: {}", member.bright_yellow());
+    }
+
+    let digests = verify_group(&mut i, algorithm, stale_files);
+    let (kept_file, reason) = match choose_keeper(&mut i.duplicate_list, &options.keep, source_dirs) {
+        Some(kept_file) => kept_file,
+        None => return 0,
+    };
+    let kept_metadata = fs::metadata(&kept_file).ok();
+    let kept_identity = kept_metadata.as_ref().and_then(file_identity);
+    let kept_attributes = kept_metadata.as_ref().and_then(LinkAttributes::new);
+
+    println!(
+        "Trying linking {} file(s) in group {} of size {}",
+        i.duplicate_list.len(),
+        i.duplicate_group_no,
+        human_bytes(i.duplicate_group_bytes_each as f64)
+    );
+
+    // A file is counted once however many of its hardlinks are in the group
+    let mut reclaimed_bytes = 0;
+    let mut linked_files = HashSet::new();
+    for (l, j) in i.duplicate_list.iter().enumerate() {
+        if digests[j] != digests[&kept_file] {
+            stale_files.push(StaleFile {
+                path: j.clone(),
+                reason: "its content differs from the file which is kept".to_owned(),
+            });
+            continue;
+        }
+        let metadata = fs::metadata(j).ok();
+        let identity = metadata.as_ref().and_then(file_identity);
+        if identity.is_some() && identity == kept_identity {
+            println!("      Already a hardlink of the kept file ({}) :: {}", l, j.bright_blue());
+            continue;
+        }
+        if let (Some(attributes), Some(kept_attributes)) = (metadata.as_ref().and_then(LinkAttributes::new), kept_attributes) {
+            if let Some(refusal) = attributes.refusal(&kept_attributes, &kept_file) {
+                failed_to_link.push(format!("Refused to link the file {} as {}", j, refusal));
+                continue;
+            }
+        }
+
+        // The space of a file is only freed once all of its hardlinks are replaced as well
+        let mut paths = vec![j];
+        for link in i.hardlinks.get(j).into_iter().flatten() {
+            if identity.is_some() && fs::metadata(link).ok().as_ref().and_then(file_identity) == identity {
+                paths.push(link);
+            } else {
+                stale_files.push(StaleFile {
+                    path: link.clone(),
+                    reason: format!("it is no longer a hardlink of {}", j),
+                });
+            }
+        }
+
+        let mut all_linked = true;
+        for path in paths {
+            if !dry_run {
+                if let Err(result) = replace_with_hardlink(&kept_file, path) {
+                    failed_to_link.push(format!("Failed to link the file {} due to {}", path, result));
+                    all_linked = false;
+                    continue;
+                }
+            }
+            if path == j {
+                println!("      Linked the file ({}) :: {}", l, path.bright_blue());
+            } else {
+                println!("          Linked its hardlink :: {}", path.bright_blue());
+            }
+        }
+        if all_linked && (identity.is_none() || linked_files.insert(identity)) {
+            reclaimed_bytes += i.duplicate_group_bytes_each as u64;
+        }
+    }
+
+    println!("\n      Kept the file :: {} (reason: {})\n", kept_file.bright_green(), reason);
+    reclaimed_bytes
+}
+
+/// What a file must share with the kept file to be replaced by a hardlink to it. A hardlink cannot cross file systems,
+/// and it has the permissions and the owner of the kept file, which the duplicate would silently take on
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct LinkAttributes {
+    device: u64,
+    mode: u32,
+    uid: u32,
+    gid: u32,
+}
+
+impl LinkAttributes {
+    /// These are not available on Windows, where the files are linked without the checks
+    fn new(metadata: &Metadata) -> Option<Self> {
+        #[cfg(target_os = "linux")]
+        {
+            Some(LinkAttributes {
+                device: metadata.dev(),
+                mode: metadata.mode() & 0o7777,
+                uid: metadata.uid(),
+                gid: metadata.gid(),
+            })
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = metadata;
+            None
+        }
+    }
+
+    // Tells why the file cannot be replaced by a hardlink to the kept file
+    fn refusal(&self, kept: &LinkAttributes, kept_file: &str) -> Option<String> {
+        if self.device != kept.device {
+            Some(format!("it is on another file system than {}", kept_file))
+        } else if self.mode != kept.mode {
+            Some(format!("its permissions {:o} differ from the {:o} of {}", self.mode, kept.mode, kept_file))
+        } else if (self.uid, self.gid) != (kept.uid, kept.gid) {
+            Some(format!("its owner {}:{} differs from the {}:{} of {}", self.uid, self.gid, kept.uid, kept.gid, kept_file))
+        } else {
+            None
+        }
+    }
+}
+
+// Replaces a file with a hardlink to the kept file. The link is made under a temporary name in the same directory, and
+// renamed over the file in one step, so the path never goes missing. A temporary name left behind by a run which
+// crashed is passed over for the next one
+fn replace_with_hardlink(kept_file: &str, path: &str) -> io::Result<()> {
+    let path = Path::new(path);
+    let name = path.file_name().map(|x| x.to_string_lossy().into_owned()).unwrap_or_default();
+    let mut attempt = 0;
+    let temp = loop {
+        let temp = path.with_file_name(format!(".{}.clonehunter-link-{}-{}", name, std::process::id(), attempt));
+        match fs::hard_link(kept_file, &temp) {
+            Ok(()) => break temp,
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists && attempt < TEMP_NAME_ATTEMPTS => attempt += 1,
+            Err(e) => return Err(e),
+        }
+    };
+
+    fs::rename(&temp, path).inspect_err(|_| {
+        let _ = fs::remove_file(&temp);
+    })
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use std::{fs::Permissions, os::unix::fs::PermissionsExt};
+
+    use tempfile::TempDir;
+
+    use super::*;
+
+    fn options(dry_run: bool) -> LinkOptions {
+        LinkOptions {
+            input_file: String::new(),
+            hard: true,
+            dry_run,
+            keep: Vec::new(),
+        }
+    }
+
+    fn write_file(dir: &TempDir, name: &str, content: &str) -> String {
+        let path = dir.path().join(name);
+        fs::write(&path, content).unwrap();
+        fs::set_permissions(&path, Permissions::from_mode(0o644)).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    fn hard_link(dir: &TempDir, original: &str, name: &str) -> String {
+        let path = dir.path().join(name);
+        fs::hard_link(original, &path).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    fn inode(path: &str) -> u64 {
+        fs::metadata(path).unwrap().ino()
+    }
+
+    fn group(files: &[String], bytes_each: usize) -> PrinterJSONObject {
+        serde_json::from_value(serde_json::json!({
+            "duplicate_group_no": 1,
+            "duplicate_group_count": files.len(),
+            "duplicate_group_bytes_each": bytes_each,
+            "duplicate_list": files,
+        }))
+        .unwrap()
+    }
+
+    fn link(group: PrinterJSONObject, dry_run: bool) -> (u64, Vec<StaleFile>, Vec<String>) {
+        let (mut stale_files, mut failed) = (Vec::new(), Vec::new());
+        let reclaimed_bytes = link_group(group, &options(dry_run), &[], HashAlgorithm::Md5, &mut stale_files, &mut failed);
+        (reclaimed_bytes, stale_files, failed)
+    }
+
+    #[test]
+    fn duplicates_become_hardlinks_of_the_kept_file() {
+        let dir = TempDir::new().unwrap();
+        let files: Vec<String> = ["a", "b", "kept"].iter().map(|x| write_file(&dir, x, "same")).collect();
+
+        let (reclaimed_bytes, stale_files, failed) = link(group(&files, 4), false);
+
+        assert!(stale_files.is_empty() && failed.is_empty());
+        assert_eq!(reclaimed_bytes, 8);
+        for file in &files[..2] {
+            assert_eq!(fs::read_to_string(file).unwrap(), "same");
+            assert_eq!(inode(file), inode(&files[2]));
+        }
+    }
+
+    #[test]
+    fn dry_run_changes_nothing() {
+        let dir = TempDir::new().unwrap();
+        let files: Vec<String> = ["a", "b", "kept"].iter().map(|x| write_file(&dir, x, "same")).collect();
+        let inodes: Vec<u64> = files.iter().map(|x| inode(x)).collect();
+
+        let (reclaimed_bytes, _, failed) = link(group(&files, 4), true);
+
+        assert!(failed.is_empty());
+        assert_eq!(reclaimed_bytes, 8);
+        assert_eq!(files.iter().map(|x| inode(x)).collect::<Vec<_>>(), inodes);
+    }
+
+    #[test]
+    fn hardlinks_are_counted_once() {
+        let dir = TempDir::new().unwrap();
+        let a = write_file(&dir, "a", "same");
+        let (also_a, listed_a) = (hard_link(&dir, &a, "also-a"), hard_link(&dir, &a, "listed-a"));
+        let kept = write_file(&dir, "kept", "same");
+        let mut group = group(&[a.clone(), listed_a.clone(), kept.clone()], 4);
+        group.hardlinks = [(a.clone(), vec![also_a.clone()])].into_iter().collect();
+
+        let (reclaimed_bytes, stale_files, failed) = link(group, false);
+
+        assert!(stale_files.is_empty() && failed.is_empty());
+        assert_eq!(reclaimed_bytes, 4);
+        assert!([&a, &also_a, &listed_a].iter().all(|x| inode(x) == inode(&kept)));
+    }
+
+    #[test]
+    fn other_file_system_or_owner_is_refused() {
+        let kept = LinkAttributes { device: 1, mode: 0o644, uid: 1000, gid: 1000 };
+
+        assert_eq!(kept.refusal(&kept, "kept"), None);
+        assert_eq!(
+            LinkAttributes { device: 2, ..kept }.refusal(&kept, "kept"),
+            Some("it is on another file system than kept".to_owned())
+        );
+        assert_eq!(
+            LinkAttributes { gid: 0, ..kept }.refusal(&kept, "kept"),
+            Some("its owner 1000:0 differs from the 1000:1000 of kept".to_owned())
+        );
+    }
+
+    #[test]
+    fn other_permissions_are_refused() {
+        let dir = TempDir::new().unwrap();
+        let (a, kept) = (write_file(&dir, "a", "same"), write_file(&dir, "kept", "same"));
+        fs::set_permissions(&kept, Permissions::from_mode(0o600)).unwrap();
+
+        let (reclaimed_bytes, _, failed) = link(group(&[a.clone(), kept.clone()], 4), false);
+
+        assert_eq!(reclaimed_bytes, 0);
+        assert_eq!(failed, vec![format!("Refused to link the file {} as its permissions 644 differ from the 600 of {}", a, kept)]);
+        assert_ne!(inode(&a), inode(&kept));
+        assert_eq!(fs::metadata(&a).unwrap().mode() & 0o7777, 0o644);
+    }
+
+    #[test]
+    fn reference_copies_are_never_linked_to() {
+        let dir = TempDir::new().unwrap();
+        let (a, b, reference) = (write_file(&dir, "a", "same"), write_file(&dir, "b", "same"), write_file(&dir, "ref", "same"));
+        let mut group = group(&[a.clone(), b.clone()], 4);
+        group.reference_list = vec![reference.clone()];
+
+        let (reclaimed_bytes, _, failed) = link(group, false);
+
+        assert!(failed.is_empty());
+        assert_eq!(reclaimed_bytes, 0);
+        assert!(inode(&a) != inode(&reference) && inode(&b) != inode(&reference) && inode(&a) != inode(&b));
+    }
+
+    #[test]
+    fn temporary_name_left_behind_is_passed_over() {
+        let dir = TempDir::new().unwrap();
+        let (a, kept) = (write_file(&dir, "a", "same"), write_file(&dir, "kept", "same"));
+        let left_behind = write_file(&dir, &format!(".a.clonehunter-link-{}-0", std::process::id()), "old");
+
+        replace_with_hardlink(&kept, &a).unwrap();
+
+        assert_eq!(inode(&a), inode(&kept));
+        assert_eq!(fs::read_to_string(left_behind).unwrap(), "old");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 3);
+    }
+}
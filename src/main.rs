@@ -8,19 +8,33 @@
 
 mod hunt;
 mod delete;
+mod dirs;
+mod similar;
+mod names;
+mod images;
+mod chunks;
+mod quarantine;
+mod link;
 
-use crate::hunt::hunt;
+use crate::hunt::{hunt, HuntConfig};
 use clap::Parser;
 use colored::Colorize;
-use clonehunter::common::{config::{Args, Command, OrderBy, OutputStyle, SortBy}, core::{
-    confirmation, log, recurse_dirs, walk_dirs, LogLevel, PrinterConfig, PrinterJSONObject, SortOrder, WalkConfig, DIR_LIST, FILES_SIZE_BYTES, FILE_LIST, VERBOSE
+use clonehunter::common::{archive::ArchiveIndex, cache::{HashCache, CACHE_SUPPORTED}, filetype::{is_known_type, is_one_of}, filter::PathFilter, trait_defs::Displayer, config::{Args, CacheAction, Command, OrderBy, OutputStyle, SortBy}, core::{
+    confirmation, file_time, group_id, log, user_id, parse_point_in_time, recurse_dirs, FileLimitingFactor, walk_dirs, LogLevel, PrinterConfig, PrinterJSONInput, PrinterJSONReport, ReportKind, REPORT_VERSION, SortOrder, WalkConfig, DIR_LIST, FILES_SIZE_BYTES, FILE_LIST, VERBOSE
 }};
 use delete::delete;
+use quarantine::{purge, undo};
+use link::link;
+use dirs::hunt_dirs;
+use similar::hunt_similar;
+use names::hunt_names;
+use images::hunt_images;
+use chunks::hunt_overlap;
 use human_bytes::human_bytes;
 use parse_size::parse_size;
 use indicatif::{ProgressBar, ProgressStyle};
 use std::{
-    env, fs::File, io::BufReader, path::PathBuf, sync::{Arc, Mutex}, time::{Duration, Instant}
+    env, fs::{self, File}, io::BufReader, path::{Path, PathBuf}, sync::{Arc, Mutex}, time::{Duration, Instant}
 };
 
 fn main() -> std::io::Result<()> {
@@ -48,6 +62,13 @@ fn main() -> std::io::Result<()> {
                     }
                     log(LogLevel::INFO, "I will sort the final output by file type\n");
                 }
+                SortBy::DetectedType => {
+                    if options.order_by.is_some() {
+                        log(LogLevel::ERROR, "--order-by cannot be used with --sort-by detected-type\n");
+                        std::process::exit(1);
+                    }
+                    log(LogLevel::INFO, "I will sort the final output by the file type detected from the content\n");
+                }
                 SortBy::FileSize => {
                     if let Some(order_by) = options.order_by {
                         match order_by {
@@ -73,6 +94,42 @@ fn main() -> std::io::Result<()> {
             }
 
 
+            if let Some(threshold) = options.similar {
+                if !(threshold > 0.0 && threshold <= 1.0) {
+                    log(LogLevel::ERROR, "--similar takes a threshold greater than 0 and at most 1, for example 0.9\n");
+                    std::process::exit(1);
+                }
+            }
+
+            if let Some(threshold) = options.similar_names {
+                if !(threshold > 0.0 && threshold <= 1.0) {
+                    log(LogLevel::ERROR, "--similar-names takes a threshold greater than 0 and at most 1, for example 0.9\n");
+                    std::process::exit(1);
+                }
+            }
+
+            if let Some(threshold) = options.overlap {
+                if !(threshold > 0.0 && threshold <= 1.0) {
+                    log(LogLevel::ERROR, "--overlap takes a threshold greater than 0 and at most 1, for example 0.5\n");
+                    std::process::exit(1);
+                }
+            }
+
+            let chunk_size = match parse_size(&options.chunk_size) {
+                Ok(size) if (256..=4 * 1024 * 1024).contains(&size) => size as u32,
+                _ => {
+                    log(LogLevel::ERROR, "--chunk-size takes a size from \"256 B\" to \"4 MiB\", for example \"64 KiB\"\n");
+                    std::process::exit(1);
+                }
+            };
+
+            // The other hunts do not look at the metadata alone, so the criteria only apply when no checksum is made
+            let match_criteria = if options.checksum || options.similar.is_some() || options.similar_names.is_some() || options.images || options.overlap.is_some() {
+                Vec::new()
+            } else {
+                options.match_on.clone()
+            };
+
             let file_max = if options.max.is_some() {
                 parse_size(options.max.clone().unwrap()).ok()
             } else {
@@ -80,15 +137,80 @@ fn main() -> std::io::Result<()> {
             };
 
             let file_min = if options.min.is_some() {
-                if options.max.is_some() {
-                    log(LogLevel::ERROR, "--max cannot be used with --min\n");
-                    std::process::exit(1);
-                }
                 parse_size(options.min.clone().unwrap()).ok()
             } else {
                 None
             };
 
+            if let (Some(min), Some(max)) = (file_min, file_max) {
+                if min >= max {
+                    log(LogLevel::ERROR, "--min must be smaller than --max\n");
+                    std::process::exit(1);
+                }
+            }
+
+            let point_in_time = |option: &str, value: &Option<String>| value.as_deref().map(|x| {
+                parse_point_in_time(x).unwrap_or_else(|e| {
+                    log(LogLevel::ERROR, format!("{} {}\n", option, e).as_str());
+                    std::process::exit(1);
+                })
+            });
+            let newer_than = point_in_time("--newer-than", &options.newer_than);
+            let older_than = point_in_time("--older-than", &options.older_than);
+
+            // The reference file is compared on the same timestamp as the files
+            let changed_since = options.changed_since.as_ref().map(|x| {
+                match fs::metadata(x).ok().and_then(|metadata| file_time(&metadata, options.time_field)) {
+                    Some(time) => time,
+                    None => {
+                        log(LogLevel::ERROR, format!("Failed to read the {} of the reference file {}\n", options.time_field, x).as_str());
+                        std::process::exit(1);
+                    }
+                }
+            });
+
+            // Owners and groups are given by their name or their numeric id
+            let owner = options.owner.as_ref().map(|x| {
+                match user_id(x) {
+                    Some(uid) if cfg!(target_os = "linux") => uid,
+                    Some(_) => {
+                        log(LogLevel::ERROR, "--owner is only supported on Linux\n");
+                        std::process::exit(1);
+                    }
+                    None => {
+                        log(LogLevel::ERROR, format!("There is no user named {}\n", x).as_str());
+                        std::process::exit(1);
+                    }
+                }
+            });
+            let group = options.group.as_ref().map(|x| {
+                match group_id(x) {
+                    Some(gid) if cfg!(target_os = "linux") => gid,
+                    Some(_) => {
+                        log(LogLevel::ERROR, "--group is only supported on Linux\n");
+                        std::process::exit(1);
+                    }
+                    None => {
+                        log(LogLevel::ERROR, format!("There is no group named {}\n", x).as_str());
+                        std::process::exit(1);
+                    }
+                }
+            });
+
+            if options.perm.is_some() && !cfg!(target_os = "linux") {
+                log(LogLevel::ERROR, "--perm is only supported on Linux\n");
+                std::process::exit(1);
+            }
+
+            let mut limits = Vec::new();
+            limits.extend(file_min.map(FileLimitingFactor::GreaterThan));
+            limits.extend(file_max.map(FileLimitingFactor::LessThan));
+            limits.extend(newer_than.into_iter().chain(changed_since).map(|x| FileLimitingFactor::NewerThan(options.time_field, x)));
+            limits.extend(older_than.map(|x| FileLimitingFactor::OlderThan(options.time_field, x)));
+            limits.extend(owner.map(FileLimitingFactor::Owner));
+            limits.extend(group.map(FileLimitingFactor::Group));
+            limits.extend(options.perm.map(FileLimitingFactor::Permissions));
+
             let pb = ProgressBar::new_spinner();
 
             pb.enable_steady_tick(Duration::from_millis(120));
@@ -114,23 +236,74 @@ fn main() -> std::io::Result<()> {
                 VERBOSE = verbose;
             }
 
-            let path = PathBuf::from(options.source_dir.clone());
+            let mut source_dirs: Vec<PathBuf> = options.source_dirs.iter().map(|x| {
+                let source_dir = PathBuf::from(x);
+                source_dir.canonicalize().unwrap_or(source_dir)
+            }).collect();
 
-            if options.no_max_depth {
-                DIR_LIST.lock().unwrap().push(path.clone());
-                recurse_dirs(&path, &WalkConfig {
-                    ext: options.extension.as_deref(),
-                    max_depth: None,
-                    max_file_size: file_max,
-                    min_file_size: file_min,
-                }, &pb, Arc::new(Mutex::new(&mut 0)));
-            } else {
-                walk_dirs(&path, threads, &WalkConfig {
-                    ext: options.extension.as_deref(),
-                    max_depth: Some(options.max_depth),
-                    max_file_size: file_max,
-                    min_file_size: file_min,
-            }, &pb, Arc::new(Mutex::new(&mut 0)));
+            // A root inside another root is already covered by the walk of the outer one
+            source_dirs.sort_by_key(|x| x.components().count());
+            let source_dirs = source_dirs.into_iter().fold(Vec::<PathBuf>::new(), |mut roots, x| {
+                if let Some(outer) = roots.iter().find(|root| x.starts_with(root)) {
+                    log(LogLevel::INFO, format!("Skipping the source directory {} as it is inside {}\n", x.to_string(), outer.to_string()).as_str());
+                } else {
+                    roots.push(x);
+                }
+                roots
+            });
+
+            let references: Vec<PathBuf> = options.references.iter().map(|x| {
+                match PathBuf::from(x).canonicalize() {
+                    Ok(reference) if source_dirs.iter().any(|source_dir| source_dir.starts_with(&reference)) => {
+                        log(LogLevel::ERROR, format!("A source directory cannot be inside the reference directory {}\n", x).as_str());
+                        std::process::exit(1);
+                    }
+                    Ok(reference) => reference,
+                    Err(e) => {
+                        log(LogLevel::ERROR, format!("Failed to read the reference directory {} due to {}\n", x, e).as_str());
+                        std::process::exit(1);
+                    }
+                }
+            }).collect();
+
+            // The ignore files above the directories hunted are not read
+            let roots: Vec<PathBuf> = source_dirs.iter().chain(references.iter()).cloned().collect();
+            if let Some(name) = options.types.iter().find(|x| !is_known_type(x)) {
+                log(LogLevel::ERROR, format!("Unknown file type {:?}. Pass a kind like image, video or document, an extension like pdf or a MIME type\n", name).as_str());
+                std::process::exit(1);
+            }
+
+            let filter = match PathFilter::new(options.extension.as_deref(), &options.include, &options.exclude, &options.exclude_regex, (!options.no_ignore).then_some(&roots[..])) {
+                Ok(filter) => filter,
+                Err(e) => {
+                    log(LogLevel::ERROR, format!("{}\n", e).as_str());
+                    std::process::exit(1);
+                }
+            };
+
+            let wc = WalkConfig {
+                filter: Arc::new(filter),
+                max_depth: if options.no_max_depth { None } else { Some(options.max_depth) },
+                limits,
+            };
+            let mut files_scanned = 0;
+            let pos = Arc::new(Mutex::new(&mut files_scanned));
+
+            for path in source_dirs.iter().chain(references.iter()) {
+                if options.no_max_depth {
+                    DIR_LIST.lock().unwrap().push(path.clone());
+                    recurse_dirs(path, &wc, &pb, pos.clone());
+                } else {
+                    walk_dirs(path, threads, &wc, &pb, pos.clone());
+                }
+            }
+
+            // A reference directory inside a source directory is walked twice
+            if !references.is_empty() {
+                FILE_LIST.lock().unwrap().sort_unstable();
+                FILE_LIST.lock().unwrap().dedup();
+                DIR_LIST.lock().unwrap().sort_unstable();
+                DIR_LIST.lock().unwrap().dedup();
             }
 
             pb.finish_with_message("Scan completed");
@@ -138,26 +311,75 @@ fn main() -> std::io::Result<()> {
             let total_files_size = FILES_SIZE_BYTES.lock().unwrap();
             println!("\n\n**** Operational Info ****\n");
             println!("Operating system                              : {}", env::consts::OS);
-            println!("The source directory you provided             : {}", options.source_dir);
+            println!("The source directories you provided           : {}", source_dirs
+                .iter()
+                .enumerate()
+                .map(|(i, x)| if source_dirs.len() > 1 { format!("[{}] {}", i + 1, x.to_string()) } else { x.to_string() })
+                .collect::<Vec<String>>()
+                .join(", "));
+            println!("Reference directories                         : {}", if options.references.is_empty() {"NA".to_owned()} else {options.references.join(", ")});
             println!("Maximum depth of directories to look for      : {}", if options.no_max_depth {"Ignored".to_owned()} else {options.max_depth.to_string()});
             println!("Total directories found in the path provided  : {}", DIR_LIST.lock().unwrap().to_vec().capacity());
             println!("Total files found in the directories          : {}", FILE_LIST.lock().unwrap().to_vec().capacity());
-            println!("Total size of source directory                : {}", human_bytes(total_files_size.unwrap_or_default() as f64));
+            println!("Total size of the files found                  : {}", human_bytes(total_files_size.unwrap_or_default() as f64));
             println!("Total threads about to be used                : {}", threads);
             println!("Perform a Checksum?                           : {}", options.checksum);
+            println!("Match the files on                            : {}", if match_criteria.is_empty() {
+                "NA".to_owned()
+            } else {
+                match_criteria.iter().map(|x| x.to_string()).collect::<Vec<String>>().join(", ")
+            });
+            println!("Hunt for identical directories?               : {}", options.dirs);
+            println!("Similarity threshold                          : {}", if let Some(threshold) = options.similar {
+                format!("{} ({})", threshold, options.similarity)
+            } else {
+                "NA".to_owned()
+            });
+            println!("File name similarity threshold                : {}", if let Some(threshold) = options.similar_names {
+                format!("{} (secondary check: {})", threshold, options.name_check)
+            } else {
+                "NA".to_owned()
+            });
+            println!("Hunt for images which look the same?          : {}", if options.images {
+                format!("true ({}, distance {})", options.image_hash, options.image_distance)
+            } else {
+                "false".to_owned()
+            });
+            println!("Share of content to find in common            : {}", if let Some(threshold) = options.overlap {
+                format!("{} (chunks of {} on average)", threshold, options.chunk_size)
+            } else {
+                "NA".to_owned()
+            });
+            println!("Report the unique files instead?              : {}", options.unique);
+            println!("Scan inside archives?                         : {}", options.scan_archives);
+            println!("Compare clones byte by byte?                  : {}", options.byte_compare);
+            println!("Hash algorithm                                : {}", if options.checksum {options.hash.to_string()} else {"NA".to_owned()});
+            println!("Use the checksum cache?                       : {}", options.checksum && !options.no_cache && CACHE_SUPPORTED);
+            println!("Hardlinks                                     : {}", options.hardlinks);
             println!("Verbose printing?                             : {}", verbose);
             println!("Target file type / Extension                  : {}", options.extension.unwrap_or("NA".to_string()));
+            println!("File types detected from the content          : {}", if options.types.is_empty() {"NA".to_owned()} else {options.types.join(", ")});
+            println!("Include patterns                              : {}", if options.include.is_empty() {"NA".to_owned()} else {options.include.join(", ")});
+            println!("Exclude patterns                              : {}", if options.exclude.is_empty() {"NA".to_owned()} else {options.exclude.join(", ")});
+            println!("Exclude regular expressions                   : {}", if options.exclude_regex.is_empty() {"NA".to_owned()} else {options.exclude_regex.join(", ")});
+            println!("Honour the ignore files?                      : {}", !options.no_ignore);
             println!("Sort by                                       : {:?}", options.sort_by);
-            println!("Order by                                      : {}", if options.order_by.is_some() {
-                options.order_by.unwrap().to_string()
+            println!("Order by                                      : {}", if let Some(order_by) = options.order_by {
+                order_by.to_string()
             } else {
                 "NA".to_owned()
             });
             println!("Target max file size                          : {}", options.max.unwrap_or("NA".to_owned()));
             println!("Target min file size                          : {}", options.min.unwrap_or("NA".to_owned()));
+            println!("Newer than                                    : {}", options.newer_than.map(|x| format!("{} ({})", x, options.time_field)).unwrap_or("NA".to_owned()));
+            println!("Older than                                    : {}", options.older_than.map(|x| format!("{} ({})", x, options.time_field)).unwrap_or("NA".to_owned()));
+            println!("Owned by                                      : {}", options.owner.clone().unwrap_or("NA".to_owned()));
+            println!("Belonging to the group                        : {}", options.group.clone().unwrap_or("NA".to_owned()));
+            println!("With the permissions                          : {}", options.perm.map(|x| x.to_string()).unwrap_or("NA".to_owned()));
+            println!("Changed since                                 : {}", options.changed_since.map(|x| format!("{} ({})", x, options.time_field)).unwrap_or("NA".to_owned()));
             println!("Output file                                   : {}", options.output_file.clone().unwrap_or("NA".to_owned()));
-            println!("Output style                                  : {}", if options.output_style.is_some() {
-                options.output_style.unwrap().to_string()
+            println!("Output style                                  : {}", if let Some(output_style) = options.output_style {
+                output_style.to_string()
             } else {
                 "NA".to_owned()
             });
@@ -166,18 +388,30 @@ fn main() -> std::io::Result<()> {
             println!("\n~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~");
 
             if confirmation() == "Y" {
-                let vec_pathbuf = FILE_LIST.lock().unwrap().to_vec();
+                let mut vec_pathbuf = FILE_LIST.lock().unwrap().to_vec();
+                // A checksum hunt checks the types once it read the first bytes of the files for their partial checksums
+                if !options.checksum {
+                    vec_pathbuf.retain(|x| is_one_of(x, &options.types));
+                }
                 let start_time = Instant::now();
                 let sort_order = SortOrder(options.sort_by, options.order_by);
+                // The types are only detected when the hunt reads the content of the files anyway
+                let flag_type_mismatches = options.checksum || !options.types.is_empty() || matches!(options.sort_by, SortBy::DetectedType);
 
-                let print_conf = if options.output_style.is_some() && options.output_file.clone().is_some() {
-                    match options.output_style.clone().unwrap() {
+                let print_conf = if let (Some(output_style), Some(output_file)) = (options.output_style, options.output_file.clone()) {
+                    match output_style {
                         OutputStyle::Default | OutputStyle::JSON  => {
-                            let file = File::create(options.output_file.unwrap()).expect("Error: Failed to create the output file you passed via --output-file option\n");
+                            let file = File::create(output_file).expect("Error: Failed to create the output file you passed via --output-file option\n");
                             PrinterConfig {
                                 file: Some(file),
                                 sort_order,
-                                output_style: options.output_style.unwrap()
+                                output_style,
+                                hash_algorithm: options.checksum.then_some(options.hash),
+                                source_dirs: source_dirs.clone(),
+                                unique: options.unique,
+                                dedupable_bytes: None,
+                                match_criteria: match_criteria.clone(),
+                                flag_type_mismatches,
                             }
                         }
                     }
@@ -186,20 +420,102 @@ fn main() -> std::io::Result<()> {
                         file: None,
                         sort_order,
                         output_style: OutputStyle::Default,
+                        hash_algorithm: options.checksum.then_some(options.hash),
+                        source_dirs: source_dirs.clone(),
+                        unique: options.unique,
+                        dedupable_bytes: None,
+                        match_criteria: match_criteria.clone(),
+                        flag_type_mismatches,
                     }
                 };
 
-                let dup_data = hunt(vec_pathbuf, options.checksum, threads, print_conf);
-                let elapsed = Some(start_time.elapsed());
+                if options.checksum && !options.no_cache && !CACHE_SUPPORTED {
+                    log(LogLevel::INFO, "The checksum cache is not supported on this platform, so every file is hashed");
+                }
+
+                let cache = if options.checksum && !options.no_cache && CACHE_SUPPORTED {
+                    match HashCache::open() {
+                        Ok(cache) => Some(cache),
+                        Err(e) => {
+                            log(LogLevel::ERROR, format!("Failed to open the checksum cache due to {}. Continuing without it", e).as_str());
+                            None
+                        }
+                    }
+                } else {
+                    None
+                };
+
+                let hunt_config = HuntConfig {
+                    checksum: options.checksum,
+                    byte_compare: options.byte_compare,
+                    hash: options.hash,
+                    cache,
+                    hardlinks: options.hardlinks,
+                    references,
+                    unique: options.unique,
+                    match_on: options.match_on.clone(),
+                    scan_archives: options.scan_archives,
+                    archives: ArchiveIndex::default(),
+                    types: options.types.clone(),
+                    threads,
+                };
+
+                let dup_data = if let Some(threshold) = options.similar {
+                    hunt_similar(vec_pathbuf, threshold, options.similarity, hunt_config, print_conf)
+                } else if let Some(threshold) = options.similar_names {
+                    hunt_names(vec_pathbuf, threshold, options.name_check, hunt_config, print_conf)
+                } else if options.images {
+                    hunt_images(vec_pathbuf, options.image_hash, options.image_distance, hunt_config, print_conf)
+                } else if let Some(threshold) = options.overlap {
+                    hunt_overlap(vec_pathbuf, threshold, chunk_size, hunt_config, print_conf)
+                } else if options.dirs {
+                    let vec_dirs = DIR_LIST.lock().unwrap().to_vec();
+                    hunt_dirs(vec_pathbuf, vec_dirs, hunt_config, print_conf)
+                } else {
+                    hunt(vec_pathbuf, hunt_config, print_conf)
+                };
+                let elapsed = start_time.elapsed();
 
                 println!("\n========= {} ==========\n", "Result".bright_blue());
 
-                log(LogLevel::INFO, format!("Time taken to finish the operation: {:?}", elapsed.unwrap()).as_str());
-                log(LogLevel::INFO, format!("Total clones found: {}", dup_data.0.to_string().bright_purple().bold().blink()).as_str());
-                log(LogLevel::INFO, format!(
-                    "Total clones file size on the disk: {}",
-                    human_bytes(dup_data.1 as f64).bright_purple().bold().blink()).as_str()
-                );
+                log(LogLevel::INFO, format!("Time taken to finish the operation: {:?}", elapsed).as_str());
+                if options.unique {
+                    log(LogLevel::INFO, format!("Total unique files found: {}", dup_data.clones_count.to_string().bright_purple().bold().blink()).as_str());
+                    log(LogLevel::INFO, format!(
+                        "Total unique files size on the disk: {}",
+                        human_bytes(dup_data.clones_total_size as f64).bright_purple().bold().blink()).as_str()
+                    );
+                } else if options.similar.is_some() || options.similar_names.is_some() || options.images {
+                    log(LogLevel::INFO, format!("Total similar files found: {}", dup_data.clones_count.to_string().bright_purple().bold().blink()).as_str());
+                } else if let Some(dedupable_size) = dup_data.dedupable_size {
+                    log(LogLevel::INFO, format!("Total files sharing content found: {}", dup_data.clones_count.to_string().bright_purple().bold().blink()).as_str());
+                    log(LogLevel::INFO, format!(
+                        "Space a deduplicating file system would save at the chunk level: {}",
+                        human_bytes(dedupable_size as f64).bright_purple().bold().blink()).as_str()
+                    );
+                } else {
+                    log(LogLevel::INFO, format!("Total clones found: {}", dup_data.clones_count.to_string().bright_purple().bold().blink()).as_str());
+                    log(LogLevel::INFO, format!(
+                        "Total clones file size on the disk: {}",
+                        human_bytes(dup_data.clones_total_size as f64).bright_purple().bold().blink()).as_str()
+                    );
+                    log(LogLevel::INFO, format!(
+                        "Space reclaimable by keeping one copy of each clone: {}",
+                        human_bytes(dup_data.reclaimable_size as f64).bright_purple().bold().blink()).as_str()
+                    );
+                    if !dup_data.owners.is_empty() {
+                        log(LogLevel::INFO, "Clones by owner:");
+                        for owner in &dup_data.owners {
+                            println!(
+                                "      {} (uid {}): {} file(s), {} reclaimable",
+                                owner.name.clone().unwrap_or("unknown".to_owned()).bright_blue(),
+                                owner.uid,
+                                owner.duplicate_files,
+                                human_bytes(owner.reclaimable_bytes as f64).bright_purple()
+                            );
+                        }
+                    }
+                }
                 println!("\nWe are done. Have a nice day 😎");
 
                 println!("\n============================\n");
@@ -208,19 +524,81 @@ fn main() -> std::io::Result<()> {
             }
         }
         Command::Delete(options) => {
-            let input_file = options.input_file;
-            if let Ok(f) = File::open(input_file) {
-                let reader = BufReader::new(f);
-                if let Ok(input_json) = serde_json::from_reader::<_,Vec<PrinterJSONObject>>(reader) {
-                    delete(input_json, options.dry_run);
-                } else {
-                    log(LogLevel::ERROR, "Failed to read the input file as JSON. Make sure it is a valid JSON");
-                }
-            } else {
-                log(LogLevel::ERROR, "The input file you have provided does not exist");
-
+            if let Some(report) = read_clone_report(&options.input_file, "delete") {
+                delete(report, &options);
+            }
+        },
+        Command::Link(options) => {
+            if let Some(report) = read_clone_report(&options.input_file, "link") {
+                link(report, &options);
+            }
+        },
+        Command::Undo(options) => undo(Path::new(&options.manifest)),
+        Command::Purge(options) => {
+            let cutoff = options.older_than.as_deref().map(|x| {
+                parse_point_in_time(x).unwrap_or_else(|e| {
+                    log(LogLevel::ERROR, format!("--older-than {}\n", e).as_str());
+                    std::process::exit(1);
+                })
+            });
+            purge(Path::new(&options.quarantine_dir), cutoff, options.dry_run);
+        },
+        Command::Cache(_) if !CACHE_SUPPORTED => {
+            log(LogLevel::ERROR, "The checksum cache is not supported on this platform");
+            std::process::exit(1);
+        },
+        Command::Cache(options) => {
+            match HashCache::open() {
+                Ok(cache) => match options.action {
+                    CacheAction::Stats => {
+                        let stats = cache.stats();
+                        println!("\n**** Checksum Cache ****\n");
+                        println!("Cache file                                    : {}", stats.file.display());
+                        println!("Cache file size                               : {}", human_bytes(stats.file_size as f64));
+                        println!("Total cached files                            : {}", stats.entries);
+                        println!("Cached partial checksums                      : {}", stats.partial_checksums);
+                        println!("Cached full checksums                         : {}", stats.full_checksums);
+                        for (algorithm, count) in stats.entries_by_algorithm {
+                            println!("Cached files hashed with {:<21}: {}", algorithm.to_string(), count);
+                        }
+                        println!();
+                    }
+                    CacheAction::Prune => {
+                        let removed = cache.prune();
+                        if let Err(e) = cache.save() {
+                            log(LogLevel::ERROR, format!("Failed to save the checksum cache due to {}", e).as_str());
+                        } else {
+                            log(LogLevel::INFO, format!("Removed {} stale entries from the checksum cache\n", removed).as_str());
+                        }
+                    }
+                },
+                Err(e) => log(LogLevel::ERROR, format!("Failed to open the checksum cache due to {}", e).as_str()),
             }
         },
     };
     Ok(())
 }
+
+// Reads the JSON report written by `hunt -u json`. A report of unique files cannot be acted upon
+fn read_clone_report(input_file: &str, action: &str) -> Option<PrinterJSONReport> {
+    if let Ok(f) = File::open(input_file) {
+        let reader = BufReader::new(f);
+        if let Ok(input_json) = serde_json::from_reader::<_, PrinterJSONInput>(reader) {
+            let report = PrinterJSONReport::from(input_json);
+            if report.version > REPORT_VERSION {
+                log(LogLevel::ERROR, format!("The input file is a version {} report, which is newer than this version of clonehunter can read. Please upgrade clonehunter", report.version).as_str());
+                std::process::exit(1);
+            }
+            if report.kind == ReportKind::Unique {
+                log(LogLevel::ERROR, format!("The input file is a report of unique files. Only a report of clones can be used to {} files", action).as_str());
+                std::process::exit(1);
+            }
+            return Some(report);
+        } else {
+            log(LogLevel::ERROR, "Failed to read the input file as JSON. Make sure it is a valid JSON");
+        }
+    } else {
+        log(LogLevel::ERROR, "The input file you have provided does not exist");
+    }
+    None
+}
@@ -0,0 +1,211 @@
+// Copyright (c) 2024 Venkatesh Omkaram
+
+use clonehunter::common::{
+    config::NameCheck,
+    core::{log, print_duplicates, DuplicatesSummary, LogLevel, PrinterConfig, SimilarPair, Stage},
+    hasher::partial_checksum,
+};
+use hashbrown::HashMap;
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use std::path::{Path, PathBuf};
+use strsim::jaro_winkler;
+
+use crate::{
+    hunt::{collapse_hardlinks, split_references, stage_stats, HuntConfig},
+    similar::group_pairs,
+};
+
+/// Characters which separate the words of a file name
+const NAME_SEPARATORS: [char; 4] = [' ', '_', '-', '.'];
+
+/// A bare number in parentheses is only taken for a copy counter, such as the " (2)" of a browser download, up to this
+/// many digits. A longer one, like the year in " (2023)", is part of the name
+const MAX_COPY_COUNTER_DIGITS: usize = 2;
+
+/// The extension, the first letter of the normalized name and the secondary check key of a file
+type Block = (String, Option<char>, Vec<u8>);
+
+/// A file name reduced to what is compared. Two files are only compared when their blocks are the same
+struct NormalizedName {
+    path: PathBuf,
+    stem: String,
+    block: Block,
+}
+
+/// Hunts for files whose names are similar, such as `report.pdf`, `report (1).pdf` and `Report_final.PDF`. The names
+/// are lowercased and stripped of their copy suffixes before they are compared with the Jaro-Winkler similarity.
+/// Only the names which share their extension, their first letter and the secondary check (the size or the partial
+/// checksum, when asked for) are compared, which keeps the number of comparisons down on big trees.
+pub fn hunt_names(
+    files: Vec<PathBuf>,
+    threshold: f64,
+    check: NameCheck,
+    config: HuntConfig,
+    print_config: PrinterConfig,
+) -> DuplicatesSummary {
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(config.threads.into())
+        .build()
+        .unwrap();
+
+    // The hardlinks of a file are the very same file, not a renamed copy
+    let (files, _) = collapse_hardlinks(files);
+
+    log(LogLevel::INFO, "Normalizing the file names");
+
+    let names: Vec<NormalizedName> = pool.install(|| {
+        files
+            .par_iter()
+            .filter_map(|path| normalize_name(path, check, &config))
+            .collect()
+    });
+
+    let mut blocks: HashMap<&Block, HashMap<&str, Vec<&Path>>> = HashMap::new();
+    for name in &names {
+        blocks
+            .entry(&name.block)
+            .or_default()
+            .entry(name.stem.as_str())
+            .or_default()
+            .push(name.path.as_path());
+    }
+
+    log(LogLevel::INFO, "Comparing the file names");
+
+    let blocks: Vec<Vec<(&str, Vec<&Path>)>> = blocks
+        .into_values()
+        .filter(|x| x.values().map(|x| x.len()).sum::<usize>() > 1)
+        .map(|x| x.into_iter().collect())
+        .collect();
+
+    let pairs: Vec<SimilarPair> = pool.install(|| {
+        blocks
+            .par_iter()
+            .flat_map_iter(|block| compare_block(block, threshold))
+            .collect()
+    });
+
+    let groups = split_references(group_pairs(pairs, Stage::FileName), &config.references);
+
+    let grouped: usize = groups.iter().map(|x| x.paths.len() + x.references.len()).sum();
+    let stages = vec![stage_stats(Stage::FileName, files.len(), grouped)];
+
+    print_duplicates(groups, stages, print_config)
+}
+
+// Pairs up the files of a block. The files sharing a normalized name are chained with a perfect score, and the first
+// file of every name stands in for the others when the names are compared
+fn compare_block(block: &[(&str, Vec<&Path>)], threshold: f64) -> Vec<SimilarPair> {
+    let mut pairs = Vec::new();
+
+    for (_, paths) in block {
+        for window in paths.windows(2) {
+            pairs.push(SimilarPair {
+                a: window[0].to_path_buf(),
+                b: window[1].to_path_buf(),
+                score: 1.0,
+                shared_bytes: None,
+            });
+        }
+    }
+
+    for (n, (stem_a, paths_a)) in block.iter().enumerate() {
+        for (stem_b, paths_b) in &block[n + 1..] {
+            let score = jaro_winkler(stem_a, stem_b);
+            if score >= threshold {
+                pairs.push(SimilarPair {
+                    a: paths_a[0].to_path_buf(),
+                    b: paths_b[0].to_path_buf(),
+                    score,
+                    shared_bytes: None,
+                });
+            }
+        }
+    }
+
+    pairs
+}
+
+// Lowercases the name, splits off the extension, strips the copy suffixes and collapses the separators into single
+// spaces. The block is made of the extension, the first letter and the secondary check of the file
+fn normalize_name(path: &Path, check: NameCheck, config: &HuntConfig) -> Option<NormalizedName> {
+    let name = path.file_name()?.to_string_lossy().to_lowercase();
+    let (stem, extension) = match name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => (stem, extension),
+        _ => (name.as_str(), ""),
+    };
+
+    let mut stem = stem.strip_prefix("copy of ").unwrap_or(stem);
+    while let Some(stripped) = strip_copy_suffix(stem) {
+        stem = stripped;
+    }
+
+    let stem = stem
+        .split(NAME_SEPARATORS)
+        .filter(|x| !x.is_empty())
+        .collect::<Vec<&str>>()
+        .join(" ");
+    let stem = if stem.is_empty() { name.clone() } else { stem };
+
+    let check_key = match check {
+        NameCheck::None => Vec::new(),
+        NameCheck::Size => path.metadata().ok()?.len().to_le_bytes().to_vec(),
+        NameCheck::PartialHash => match partial_checksum(path, config.hash) {
+            Ok(digest) => digest.0,
+            Err(e) => {
+                log(LogLevel::ERROR, format!("File {:?} {:?}", path, e.kind()).as_str());
+                return None;
+            }
+        },
+    };
+
+    Some(NormalizedName {
+        path: path.to_path_buf(),
+        block: (extension.to_owned(), stem.chars().next(), check_key),
+        stem,
+    })
+}
+
+// Strips one copy suffix such as " (1)", " (copy 2)", " - copy" or "_copy3" from the end of a lowercased name
+fn strip_copy_suffix(stem: &str) -> Option<&str> {
+    let trimmed = stem.trim_end();
+
+    if let Some((before, inside)) = trimmed.strip_suffix(')').and_then(|x| x.rsplit_once('(')) {
+        let inside = inside.trim();
+        let is_number = |x: &str| x.chars().all(|c| c.is_ascii_digit());
+        let is_copy = match inside.strip_prefix("copy") {
+            Some(number) => is_number(number.trim()),
+            None => !inside.is_empty() && inside.len() <= MAX_COPY_COUNTER_DIGITS && is_number(inside),
+        };
+        if is_copy && !before.trim().is_empty() {
+            return Some(before.trim_end());
+        }
+    }
+
+    let before = trimmed
+        .trim_end_matches(|c: char| c.is_ascii_digit())
+        .trim_end()
+        .strip_suffix("copy")?;
+    let stripped = before.trim_end_matches(NAME_SEPARATORS);
+    (stripped.len() < before.len() && !stripped.is_empty()).then_some(stripped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn copy_suffixes_are_stripped() {
+        for name in ["budget (1)", "budget (12)", "budget (copy)", "budget (copy 2)", "budget - copy", "budget_copy3"] {
+            assert_eq!(strip_copy_suffix(name), Some("budget"), "{}", name);
+        }
+        assert_eq!(strip_copy_suffix("budget (2023) (1)"), Some("budget (2023)"));
+    }
+
+    #[test]
+    fn other_suffixes_are_kept() {
+        for name in ["budget (2023)", "budget (2024)", "budget (v2)", "budget ()", "(1)", "budget 2"] {
+            assert_eq!(strip_copy_suffix(name), None, "{}", name);
+        }
+    }
+}
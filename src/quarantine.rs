@@ -0,0 +1,454 @@
+// Copyright (c) 2024 Venkatesh Omkaram
+
+// The quarantine keeps the files which `delete --quarantine` took away, for the servers which have no trash. Every run
+// moves its files into a tree of its own which mirrors their original paths, and writes a manifest next to the tree
+// which `undo` restores the files from and `purge` empties the quarantine with
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, ErrorKind},
+    path::{self, Component, Path, PathBuf},
+    time::SystemTime,
+};
+
+use chrono::Local;
+use clonehunter::common::{
+    config::HashAlgorithm,
+    core::{confirmation, log, LogLevel},
+    hasher::{full_checksum, Digest},
+    trait_defs::Displayer,
+};
+use colored::Colorize;
+use human_bytes::human_bytes;
+use serde::{Deserialize, Serialize};
+
+/// The manifest of a run is named after its tree, with this suffix
+const MANIFEST_SUFFIX: &str = ".manifest.json";
+
+/// The record of the files a run of `delete` moved into the quarantine
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Manifest {
+    /// The mirrored tree the files of the run were moved into
+    pub tree: String,
+    /// The algorithm of the checksums
+    pub hash_algorithm: HashAlgorithm,
+    pub entries: Vec<ManifestEntry>,
+}
+
+/// A file moved into the quarantine
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ManifestEntry {
+    pub original_path: String,
+    pub quarantined_path: String,
+    /// Hex encoded checksum of the content
+    pub checksum: String,
+    /// When the file was moved, in RFC 3339
+    pub quarantined_at: String,
+}
+
+/// Moves the files of a run of `delete` into the quarantine directory and keeps their manifest
+pub struct Quarantine {
+    manifest_path: PathBuf,
+    manifest: Manifest,
+}
+
+impl Quarantine {
+    /// Names the tree and the manifest of a new run after the time it started. Nothing is written until a file is moved
+    pub fn new(dir: &Path, algorithm: HashAlgorithm) -> io::Result<Self> {
+        let dir = path::absolute(dir)?;
+        let started = Local::now().format("%Y-%m-%dT%H-%M-%S").to_string();
+
+        let mut run = started.clone();
+        let mut attempt = 1;
+        while dir.join(&run).exists() || dir.join(format!("{}{}", run, MANIFEST_SUFFIX)).exists() {
+            attempt += 1;
+            run = format!("{}-{}", started, attempt);
+        }
+
+        Ok(Quarantine {
+            manifest_path: dir.join(format!("{}{}", run, MANIFEST_SUFFIX)),
+            manifest: Manifest {
+                tree: dir.join(run).to_string(),
+                hash_algorithm: algorithm,
+                entries: Vec::new(),
+            },
+        })
+    }
+
+    pub fn manifest_path(&self) -> &Path {
+        &self.manifest_path
+    }
+
+    pub fn tree(&self) -> &str {
+        &self.manifest.tree
+    }
+
+    /// Moves a file into the tree of the run, under the same path it had below the root
+    pub fn add(&mut self, path: &str, digest: &Digest) -> io::Result<()> {
+        let quarantined_path = mirrored_path(Path::new(&self.manifest.tree), Path::new(path));
+        move_file(Path::new(path), &quarantined_path)?;
+
+        self.manifest.entries.push(ManifestEntry {
+            original_path: path.to_owned(),
+            quarantined_path: quarantined_path.to_string(),
+            checksum: digest.to_string(),
+            quarantined_at: humantime::format_rfc3339_seconds(SystemTime::now()).to_string(),
+        });
+        Ok(())
+    }
+
+    /// Writes the manifest, once a file was moved
+    pub fn save(&self) -> io::Result<()> {
+        if self.manifest.entries.is_empty() {
+            return Ok(());
+        }
+        write_manifest(&self.manifest_path, &self.manifest)
+    }
+}
+
+/// Moves the files of a manifest back to where they came from. A file is not restored over a file which took its place.
+/// The manifest keeps the files which could not be restored, and is removed along with its tree once all of them are
+pub fn undo(manifest_path: &Path) {
+    let mut manifest = match read_manifest(manifest_path) {
+        Ok(manifest) => manifest,
+        Err(e) => {
+            log(LogLevel::ERROR, format!("Failed to read the manifest {:?} due to {}", manifest_path, e).as_str());
+            return;
+        }
+    };
+
+    log(LogLevel::INFO, format!("Restoring {} file(s) from {}", manifest.entries.len(), manifest.tree).as_str());
+
+    let mut failed_to_restore = Vec::new();
+    let algorithm = manifest.hash_algorithm;
+    manifest.entries.retain(|entry| {
+        let original_path = Path::new(&entry.original_path);
+        let quarantined_path = Path::new(&entry.quarantined_path);
+        if original_path.exists() {
+            failed_to_restore.push(format!("Did not restore {} as another file took its place", entry.original_path));
+            return true;
+        }
+        // The content is restored either way, but a change is worth knowing about
+        if full_checksum(quarantined_path, algorithm).is_ok_and(|x| x.to_string() != entry.checksum) {
+            println!("      The content of {} changed while it was in the quarantine", entry.quarantined_path.bright_yellow());
+        }
+        match move_file(quarantined_path, original_path) {
+            Ok(()) => {
+                println!("      Restored the file :: {}", entry.original_path.bright_green());
+                false
+            }
+            Err(e) => {
+                failed_to_restore.push(format!("Failed to restore the file {} due to {}", entry.original_path, e));
+                true
+            }
+        }
+    });
+
+    finish_manifest(manifest_path, &manifest);
+
+    if !failed_to_restore.is_empty() {
+        println!("\n## {} ##\n", "Error: Some files were not restored, so they are still in the manifest. Here is the list".bright_red().bold());
+        for i in failed_to_restore {
+            eprintln!("{}", i.bright_magenta());
+        }
+    } else {
+        println!("\nEverything was restored.\n");
+    }
+}
+
+/// Deletes for good the files which were moved into the quarantine directory before the cutoff, or all of them without
+/// one. The manifests are updated, and removed along with their trees once they are empty
+pub fn purge(dir: &Path, cutoff: Option<SystemTime>, dry_run: bool) {
+    let mut manifests = Vec::new();
+    match fs::read_dir(dir) {
+        Ok(entries) => {
+            for entry in entries.flatten() {
+                let path = entry.path();
+                if path.to_string_lossy().ends_with(MANIFEST_SUFFIX) {
+                    match read_manifest(&path) {
+                        Ok(manifest) => manifests.push((path, manifest)),
+                        Err(e) => log(LogLevel::ERROR, format!("Failed to read the manifest {:?} due to {}", path, e).as_str()),
+                    }
+                }
+            }
+        }
+        Err(e) => {
+            log(LogLevel::ERROR, format!("Failed to read the quarantine directory {:?} due to {}", dir, e).as_str());
+            return;
+        }
+    }
+
+    let expired: Vec<&ManifestEntry> =
+        manifests.iter().flat_map(|x| x.1.entries.iter()).filter(|x| is_expired(x, cutoff)).collect();
+    let expired_bytes: u64 = expired
+        .iter()
+        .filter_map(|x| fs::metadata(&x.quarantined_path).ok())
+        .map(|x| x.len())
+        .sum();
+
+    log(LogLevel::INFO, format!("Is this a dry run? : {}", dry_run.to_string().blink()).as_str());
+    log(
+        LogLevel::INFO,
+        format!(
+            "Found {} file(s) of {} to purge in {} manifest(s)",
+            expired.len(),
+            human_bytes(expired_bytes as f64).blink(),
+            manifests.len()
+        )
+        .as_str(),
+    );
+
+    if expired.is_empty() {
+        println!("\nThe quarantine holds nothing to purge.\n");
+        return;
+    }
+
+    println!("{}", "\nShall I proceed to delete these files for good?".bright_blue());
+    if confirmation() != "Y" {
+        println!("Phew... You QUIT!\n");
+        return;
+    }
+
+    let mut failed_to_delete = Vec::new();
+    for (manifest_path, manifest) in manifests {
+        purge_manifest(&manifest_path, manifest, cutoff, dry_run, &mut failed_to_delete);
+    }
+
+    if !failed_to_delete.is_empty() && !dry_run {
+        println!("\n## {} ##\n", "Error: Looks like there were some failures while purging. Here is the list".bright_red().bold());
+        for i in failed_to_delete {
+            eprintln!("{}", i.bright_magenta());
+        }
+    } else if dry_run {
+        log(LogLevel::INFO, "Nothing changed. This was a dry run.\n");
+    } else {
+        println!("\nThe quarantine was purged.\n");
+    }
+}
+
+// Whether a file was moved into the quarantine before the cutoff. Every file is without a cutoff, and a file whose time
+// cannot be read is kept
+fn is_expired(entry: &ManifestEntry, cutoff: Option<SystemTime>) -> bool {
+    match (cutoff, humantime::parse_rfc3339(&entry.quarantined_at)) {
+        (None, _) => true,
+        (Some(cutoff), Ok(quarantined_at)) => quarantined_at <= cutoff,
+        (Some(_), Err(_)) => false,
+    }
+}
+
+// Deletes the files of a manifest which were moved before the cutoff, and updates the manifest
+fn purge_manifest(
+    manifest_path: &Path,
+    mut manifest: Manifest,
+    cutoff: Option<SystemTime>,
+    dry_run: bool,
+    failed_to_delete: &mut Vec<String>,
+) {
+    manifest.entries.retain(|entry| {
+        if !is_expired(entry, cutoff) {
+            return true;
+        }
+        if dry_run {
+            println!("      Purged the file :: {}", entry.quarantined_path.bright_blue());
+            return true;
+        }
+        match fs::remove_file(&entry.quarantined_path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => {
+                failed_to_delete.push(format!("Failed to delete the file {} due to {}", entry.quarantined_path, e));
+                true
+            }
+            _ => {
+                println!("      Purged the file :: {}", entry.quarantined_path.bright_blue());
+                false
+            }
+        }
+    });
+    if !dry_run {
+        finish_manifest(manifest_path, &manifest);
+    }
+}
+
+// Writes back the entries left in a manifest, or removes the manifest and what is left of its tree when none are
+fn finish_manifest(manifest_path: &Path, manifest: &Manifest) {
+    let result = if manifest.entries.is_empty() {
+        remove_empty_dirs(Path::new(&manifest.tree));
+        fs::remove_file(manifest_path)
+    } else {
+        write_manifest(manifest_path, manifest)
+    };
+    if let Err(e) = result {
+        log(LogLevel::ERROR, format!("Failed to update the manifest {:?} due to {}", manifest_path, e).as_str());
+    }
+}
+
+fn read_manifest(path: &Path) -> io::Result<Manifest> {
+    Ok(serde_json::from_reader(BufReader::new(File::open(path)?))?)
+}
+
+fn write_manifest(path: &Path, manifest: &Manifest) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    Ok(serde_json::to_writer_pretty(BufWriter::new(File::create(path)?), manifest)?)
+}
+
+// The path a file takes in a mirrored tree, i.e. its absolute path with the root and the drive turned into directories
+fn mirrored_path(tree: &Path, path: &Path) -> PathBuf {
+    let mut mirrored = tree.to_path_buf();
+    for component in path.components() {
+        match component {
+            Component::Prefix(prefix) => mirrored.push(prefix.as_os_str().to_string_lossy().replace([':', '\\', '?'], "")),
+            Component::Normal(name) => mirrored.push(name),
+            Component::RootDir | Component::CurDir | Component::ParentDir => {}
+        }
+    }
+    mirrored
+}
+
+// Moves a file without ever replacing another one. A file moved across file systems is copied instead
+fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+    if to.symlink_metadata().is_ok() {
+        return Err(io::Error::new(ErrorKind::AlreadyExists, format!("{:?} already exists", to)));
+    }
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    match fs::rename(from, to) {
+        Err(e) if e.kind() == ErrorKind::CrossesDevices => copy_then_remove(from, to),
+        result => result,
+    }
+}
+
+// Copies a file along with its modified time, and removes it once the copy is complete
+fn copy_then_remove(from: &Path, to: &Path) -> io::Result<()> {
+    fs::copy(from, to)?;
+    File::options().write(true).open(to)?.set_modified(fs::metadata(from)?.modified()?)?;
+    fs::remove_file(from)
+}
+
+// Removes the directories left empty under a tree, and the tree itself when it ends up empty
+fn remove_empty_dirs(dir: &Path) {
+    if let Ok(entries) = fs::read_dir(dir) {
+        for entry in entries.flatten() {
+            if entry.file_type().is_ok_and(|x| x.is_dir()) {
+                remove_empty_dirs(&entry.path());
+            }
+        }
+    }
+    let _ = fs::remove_dir(dir);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tempfile::TempDir;
+
+    use super::*;
+
+    // Writes a file and returns its path along with the checksum of its content
+    fn write_file(dir: &TempDir, name: &str, content: &str) -> (String, Digest) {
+        let path = dir.path().join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, content).unwrap();
+        let digest = full_checksum(&path, HashAlgorithm::Md5).unwrap();
+        (path.to_string(), digest)
+    }
+
+    fn entry(dir: &TempDir, name: &str, quarantined_at: SystemTime) -> ManifestEntry {
+        let (path, digest) = write_file(dir, &format!("quarantine/run/{}", name), name);
+        ManifestEntry {
+            original_path: dir.path().join(name).to_string(),
+            quarantined_path: path,
+            checksum: digest.to_string(),
+            quarantined_at: humantime::format_rfc3339_seconds(quarantined_at).to_string(),
+        }
+    }
+
+    #[test]
+    fn undo_restores_the_mirrored_files() {
+        let dir = TempDir::new().unwrap();
+        let (path, digest) = write_file(&dir, "photos/a.jpg", "content");
+        let mut quarantine = Quarantine::new(&dir.path().join("quarantine"), HashAlgorithm::Md5).unwrap();
+
+        quarantine.add(&path, &digest).unwrap();
+        quarantine.save().unwrap();
+
+        let quarantined_path = mirrored_path(Path::new(quarantine.tree()), Path::new(&path));
+        assert!(quarantined_path.ends_with("photos/a.jpg"));
+        assert!(!Path::new(&path).exists());
+        assert_eq!(fs::read_to_string(&quarantined_path).unwrap(), "content");
+
+        let manifest = read_manifest(quarantine.manifest_path()).unwrap();
+        assert_eq!(manifest.entries.len(), 1);
+        assert_eq!(manifest.entries[0].original_path, path);
+        assert_eq!(manifest.entries[0].quarantined_path, quarantined_path.to_string());
+        assert_eq!(manifest.entries[0].checksum, digest.to_string());
+
+        undo(quarantine.manifest_path());
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "content");
+        assert!(!quarantine.manifest_path().exists());
+        assert!(!Path::new(quarantine.tree()).exists());
+    }
+
+    #[test]
+    fn undo_does_not_overwrite_a_file_which_took_the_place() {
+        let dir = TempDir::new().unwrap();
+        let (path, digest) = write_file(&dir, "a.txt", "quarantined");
+        let mut quarantine = Quarantine::new(&dir.path().join("quarantine"), HashAlgorithm::Md5).unwrap();
+        quarantine.add(&path, &digest).unwrap();
+        quarantine.save().unwrap();
+        fs::write(&path, "new").unwrap();
+
+        undo(quarantine.manifest_path());
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        let manifest = read_manifest(quarantine.manifest_path()).unwrap();
+        assert_eq!(manifest.entries.len(), 1);
+        assert_eq!(fs::read_to_string(&manifest.entries[0].quarantined_path).unwrap(), "quarantined");
+    }
+
+    #[test]
+    fn purge_keeps_the_files_moved_after_the_cutoff() {
+        let dir = TempDir::new().unwrap();
+        let now = SystemTime::now();
+        let day = Duration::from_secs(24 * 60 * 60);
+        let manifest_path = dir.path().join(format!("quarantine/run{}", MANIFEST_SUFFIX));
+        let manifest = Manifest {
+            tree: dir.path().join("quarantine/run").to_string(),
+            hash_algorithm: HashAlgorithm::Md5,
+            entries: vec![entry(&dir, "old", now - 10 * day), entry(&dir, "new", now - day)],
+        };
+        let (old, new) = (manifest.entries[0].quarantined_path.clone(), manifest.entries[1].quarantined_path.clone());
+        let mut failed = Vec::new();
+
+        purge_manifest(&manifest_path, manifest, Some(now - 5 * day), false, &mut failed);
+
+        assert!(failed.is_empty());
+        assert!(!Path::new(&old).exists() && Path::new(&new).exists());
+        let manifest = read_manifest(&manifest_path).unwrap();
+        assert_eq!(manifest.entries.len(), 1);
+        assert_eq!(manifest.entries[0].quarantined_path, new);
+
+        purge_manifest(&manifest_path, manifest, None, false, &mut failed);
+
+        assert!(failed.is_empty());
+        assert!(!Path::new(&new).exists());
+        assert!(!manifest_path.exists() && !dir.path().join("quarantine/run").exists());
+    }
+
+    #[test]
+    fn copy_keeps_the_content_and_modified_time() {
+        let dir = TempDir::new().unwrap();
+        let (from, _) = write_file(&dir, "a.txt", "content");
+        let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        File::options().write(true).open(&from).unwrap().set_modified(modified).unwrap();
+        let to = dir.path().join("b.txt");
+
+        copy_then_remove(Path::new(&from), &to).unwrap();
+
+        assert!(!Path::new(&from).exists());
+        assert_eq!(fs::read_to_string(&to).unwrap(), "content");
+        assert_eq!(fs::metadata(&to).unwrap().modified().unwrap(), modified);
+    }
+}
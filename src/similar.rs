@@ -0,0 +1,244 @@
+// Copyright (c) 2024 Venkatesh Omkaram
+
+use clonehunter::common::{
+    config::SimilarityMetric,
+    core::{log, print_duplicates, CloneGroup, DuplicatesSummary, LogLevel, PrinterConfig, SimilarPair, Stage},
+};
+use hashbrown::{HashMap, HashSet};
+use indicatif::ProgressBar;
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+use strsim::{generic_jaro_winkler, generic_levenshtein};
+
+use crate::hunt::{collapse_hardlinks, progress_style, split_references, stage_stats, HuntConfig};
+
+/// Text files larger than this are left out of the similarity hunt
+const SIMILAR_MAX_FILE_SIZE: u64 = 1024 * 1024;
+
+/// Number of bytes looked at to decide whether a file holds text
+const TEXT_SNIFF_LENGTH: usize = 8 * 1024;
+
+/// A line found in more files than this (blank lines, closing braces, licence headers, or the lines of a file copied to
+/// many places) does not make all of its files candidates of each other
+const COMMON_LINE_LIMIT: usize = 64;
+
+/// The files sharing a common line are sorted by their length, and each of them is only compared with this many of the
+/// files which follow it. The many copies of a file still end up in one group this way, through the pairs they form
+const COMMON_LINE_WINDOW: usize = 8;
+
+/// A text file reduced to the hashes of its lines. Blank lines and the whitespace around the lines are ignored
+struct TextFile {
+    path: PathBuf,
+    size: u64,
+    lines: Vec<u64>,
+}
+
+/// Hunts for text files whose content is similar without being identical. Every text file is reduced to a list of
+/// line hashes, and two files are only compared when they share a line, and for the Levenshtein distance when their
+/// sizes and line counts are close enough for the threshold to be reachable. A line common to many files only pairs
+/// the files which are next to each other by length. The candidate pairs are scored over their lines with the chosen
+/// metric, and the pairs scoring at least the threshold are joined into groups.
+pub fn hunt_similar(
+    files: Vec<PathBuf>,
+    threshold: f64,
+    metric: SimilarityMetric,
+    config: HuntConfig,
+    print_config: PrinterConfig,
+) -> DuplicatesSummary {
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(config.threads.into())
+        .build()
+        .unwrap();
+
+    // The hardlinks of a file are the very same file, not a similar one
+    let (files, _) = collapse_hardlinks(files);
+
+    log(LogLevel::INFO, "Reading the text files");
+
+    let pb = ProgressBar::new(files.len() as u64);
+    pb.set_style(progress_style());
+    pb.set_message("text files read");
+
+    let text_files: Vec<TextFile> = pool.install(|| {
+        files
+            .par_iter()
+            .filter_map(|path| {
+                let text_file = read_text_file(path);
+                pb.inc(1);
+                text_file
+            })
+            .collect()
+    });
+
+    pb.finish_and_clear();
+
+    log(LogLevel::INFO, format!("Found {} text file(s) out of {}", text_files.len(), files.len()).as_str());
+
+    let mut files_by_line: HashMap<u64, Vec<usize>> = HashMap::new();
+    for (i, text_file) in text_files.iter().enumerate() {
+        for line in text_file.lines.iter().collect::<HashSet<_>>() {
+            files_by_line.entry(*line).or_default().push(i);
+        }
+    }
+
+    let mut candidates: HashSet<(usize, usize)> = HashSet::new();
+    for indices in files_by_line.values().filter(|x| x.len() > 1) {
+        let mut indices = indices.clone();
+        let window = if indices.len() > COMMON_LINE_LIMIT {
+            indices.sort_by_key(|&x| (text_files[x].lines.len(), text_files[x].size, x));
+            COMMON_LINE_WINDOW
+        } else {
+            indices.len()
+        };
+        for (n, &a) in indices.iter().enumerate() {
+            for &b in indices[n + 1..].iter().take(window) {
+                if could_be_similar(&text_files[a], &text_files[b], threshold, metric) {
+                    candidates.insert((a.min(b), a.max(b)));
+                }
+            }
+        }
+    }
+
+    log(LogLevel::INFO, format!("Comparing {} candidate pair(s)", candidates.len()).as_str());
+
+    let pb = ProgressBar::new(candidates.len() as u64);
+    pb.set_style(progress_style());
+    pb.set_message("pairs compared");
+
+    let candidates: Vec<(usize, usize)> = candidates.into_iter().collect();
+    let pairs: Vec<(usize, usize, f64)> = pool.install(|| {
+        candidates
+            .par_iter()
+            .filter_map(|&(a, b)| {
+                let score = similarity(&text_files[a], &text_files[b], metric);
+                pb.inc(1);
+                (score >= threshold).then_some((a, b, score))
+            })
+            .collect()
+    });
+
+    pb.finish_and_clear();
+
+    let pairs = pairs
+        .into_iter()
+        .map(|(a, b, score)| SimilarPair {
+            a: text_files[a].path.clone(),
+            b: text_files[b].path.clone(),
+            score,
+            shared_bytes: None,
+        })
+        .collect();
+    let groups = group_pairs(pairs, Stage::Similarity);
+    let groups = split_references(groups, &config.references);
+
+    let grouped: usize = groups.iter().map(|x| x.paths.len() + x.references.len()).sum();
+    let stages = vec![stage_stats(Stage::Similarity, text_files.len(), grouped)];
+
+    print_duplicates(groups, stages, print_config)
+}
+
+// Reads a file as text. Empty files, large files and files with a NUL byte near the start are skipped
+fn read_text_file(path: &Path) -> Option<TextFile> {
+    let size = path.metadata().ok()?.len();
+    if size == 0 || size > SIMILAR_MAX_FILE_SIZE {
+        return None;
+    }
+
+    let content = match fs::read(path) {
+        Ok(content) => content,
+        Err(e) => {
+            log(LogLevel::ERROR, format!("File {:?} {:?}", path, e.kind()).as_str());
+            return None;
+        }
+    };
+
+    if content[..content.len().min(TEXT_SNIFF_LENGTH)].contains(&0) {
+        return None;
+    }
+
+    let lines: Vec<u64> = String::from_utf8_lossy(&content)
+        .lines()
+        .map(str::trim)
+        .filter(|x| !x.is_empty())
+        .map(fxhash::hash64)
+        .collect();
+
+    (!lines.is_empty()).then(|| TextFile {
+        path: path.to_path_buf(),
+        size,
+        lines,
+    })
+}
+
+// The line edit distance is at least the difference of the line counts, so files whose line counts are too far apart
+// cannot reach the threshold. The size ratio is a cheaper rule of thumb along the same lines. The Jaro-Winkler
+// similarity has no such bound, so every pair is compared
+fn could_be_similar(a: &TextFile, b: &TextFile, threshold: f64, metric: SimilarityMetric) -> bool {
+    let ratio = |x: usize, y: usize| x.min(y) as f64 / x.max(y) as f64;
+    match metric {
+        SimilarityMetric::Levenshtein => {
+            ratio(a.lines.len(), b.lines.len()) >= threshold && ratio(a.size as usize, b.size as usize) >= threshold
+        }
+        SimilarityMetric::JaroWinkler => true,
+    }
+}
+
+fn similarity(a: &TextFile, b: &TextFile, metric: SimilarityMetric) -> f64 {
+    match metric {
+        SimilarityMetric::Levenshtein => {
+            let longest = a.lines.len().max(b.lines.len());
+            1.0 - generic_levenshtein(&a.lines, &b.lines) as f64 / longest as f64
+        }
+        SimilarityMetric::JaroWinkler => generic_jaro_winkler(&a.lines, &b.lines),
+    }
+}
+
+/// Joins the similar pairs into groups. Files which are similar to a common file end up in the same group, which lists
+/// its pairs from the best score to the worst
+pub fn group_pairs(pairs: Vec<SimilarPair>, stage: Stage) -> Vec<CloneGroup> {
+    let mut indices: HashMap<PathBuf, usize> = HashMap::new();
+    for pair in &pairs {
+        for path in [&pair.a, &pair.b] {
+            let next = indices.len();
+            indices.entry_ref(path).or_insert(next);
+        }
+    }
+
+    let mut parents: Vec<usize> = (0..indices.len()).collect();
+    for pair in &pairs {
+        let (root_a, root_b) = (find_root(&mut parents, indices[&pair.a]), find_root(&mut parents, indices[&pair.b]));
+        parents[root_b] = root_a;
+    }
+
+    let mut pairs_by_root: HashMap<usize, Vec<SimilarPair>> = HashMap::new();
+    for pair in pairs {
+        pairs_by_root.entry(find_root(&mut parents, indices[&pair.a])).or_default().push(pair);
+    }
+
+    pairs_by_root
+        .into_values()
+        .map(|mut pairs| {
+            pairs.sort_by(|x, y| y.score.total_cmp(&x.score));
+
+            let mut paths: Vec<PathBuf> = pairs.iter().flat_map(|x| [x.a.clone(), x.b.clone()]).collect();
+            paths.sort();
+            paths.dedup();
+            let bytes_each = paths.iter().filter_map(|x| x.metadata().ok()).map(|x| x.len()).max().unwrap_or_default();
+
+            let mut group = CloneGroup::new(bytes_each, paths, stage, None);
+            group.pairs = pairs;
+            group
+        })
+        .collect()
+}
+
+fn find_root(parents: &mut [usize], mut i: usize) -> usize {
+    while parents[i] != i {
+        parents[i] = parents[parents[i]];
+        i = parents[i];
+    }
+    i
+}